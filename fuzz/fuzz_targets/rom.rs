@@ -0,0 +1,36 @@
+//! Runs arbitrary bytes as a ROM, checking that the interpreter reports bad programs through
+//! `Chip8Error` rather than panicking.
+
+#![no_main]
+
+use chip8::{Chip8, Quirks};
+use libfuzzer_sys::fuzz_target;
+
+/// Enough to get through loops, while keeping each input quick.
+const MAX_STEPS: usize = 10_000;
+
+fuzz_target!(|data: &[u8]| {
+    // The first byte picks the platform, so that each preset's quirks are covered.
+    let Some((&platform, rom)) = data.split_first() else {
+        return;
+    };
+    let quirks = match platform % 3 {
+        0 => Quirks::default(),
+        1 => Quirks::superchip(),
+        _ => Quirks::xochip(),
+    };
+    // A new machine for every input, so that nothing carries over between them.
+    let mut chip8 = Chip8::new(quirks);
+    if chip8.load_rom(rom).is_err() {
+        return;
+    }
+    for step in 0..MAX_STEPS {
+        // Tick the timers every step so the display wait never holds things up, and press a key
+        // now and then so that `FX0A` gets past waiting.
+        chip8.tick_timers();
+        chip8.set_key((step % 16) as u8, step % 32 < 16);
+        if chip8.step().is_err() {
+            break;
+        }
+    }
+});
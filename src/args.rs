@@ -0,0 +1,825 @@
+//! Command line argument parsing.
+
+use std::{path::PathBuf, str::FromStr, time::Duration};
+
+use chip8::{disasm::mnemonic, Quirks, DEFAULT_CYCLES_PER_FRAME, DEFAULT_HZ, FONT_END};
+
+use crate::{
+    debug::{DEFAULT_HOOK_OPCODE, DEFAULT_REWIND},
+    input::{KeyMap, DEFAULT_KEY_HOLD},
+    render::{RenderMode, Theme, DEFAULT_MAX_FPS},
+};
+
+pub const USAGE: &str = "\
+usage: chip8 [options] <rom>
+
+Pass `-` as <rom> to read the ROM from stdin.
+
+options:
+    --hz <n>          instructions executed per second (default 700)
+    --frame-timing    execute instructions in 60Hz frames, drawing once at the
+                      end of each frame
+    --cycles-per-frame <n>
+                      instructions executed per frame with frame timing
+                      (default 11), implies `--frame-timing`
+    --mute            disable sound
+    --beep-bell       ring the terminal bell instead of playing sound, for
+                      machines without a sound device
+    --no-display      run without drawing the display, printing status messages
+                      to stderr
+    --window          draw the display in a window instead of the terminal,
+                      printing status messages to stderr (needs the `window`
+                      feature)
+    --max-cycles <n>  exit successfully after executing <n> instructions, or
+                      when the program halts
+    --disassemble     print a listing of the ROM instead of running it
+    --verify          report opcodes in the ROM which aren't supported instead of
+                      running it
+    --bench           run the ROM as fast as possible for a few seconds and
+                      report the instructions executed per second
+    --debug           start paused in the step debugger
+    --rewind <n>      instructions the debugger can step back through (default
+                      64)
+    --trace <file>    append a line to <file> for every instruction executed
+    --record-input <file>
+                      record key presses to <file> for `--replay`
+    --replay <file>   play back key presses recorded with `--record-input`
+    --dump-state-on-exit
+                      print the registers, timers and stack to stderr on exit
+    --profile         count how often each opcode is executed and print a table
+                      of them to stderr on exit
+    --skip-unknown    carry on past unknown opcodes rather than stopping, listing
+                      them on exit
+    --test-hooks      print a hash of the display to stderr whenever the ROM
+                      executes the hook opcode, as checkpoints for test ROMs
+    --hook-opcode <opcode>
+                      the opcode for `--test-hooks`, in hex, which must be one
+                      the emulator doesn't implement (default 0F00)
+    --keys <map>      keyboard keys for the keypad, as `0=x,1=1,...,F=v`
+    --key-hold <ms>   how long keys stay down in terminals which can't report
+                      key releases (default 120)
+    --quirks <preset> behave like `chip8` (default), `superchip` or `xochip`
+    --accurate        behave as faithfully to the COSMAC VIP as possible, the
+                      same as `--quirks chip8`
+    --quirk <name>[=on|off]
+                      turn one of the preset's quirks on or off, whichever
+                      preset is used: `vf-reset`, `memory-increment`,
+                      `shift`, `jump-vx`, `wrap-sprites`, `index-overflow` or
+                      `display-wait`
+    --detect-quirks   use the right preset for known games, rather than
+                      `--quirks`
+    --memory <bytes>  memory size, overriding the preset's 4KB (64KB for
+                      `xochip`)
+    --load-address <addr>
+                      where the ROM is loaded and starts, e.g. 0x600 for ETI
+                      660 programs (default 0x200)
+    --byte-swap       swap the bytes of each instruction in the ROM as it's
+                      loaded, for ROMs dumped byte-swapped by some tools
+    --stack-depth <n> how many subroutine calls can be nested, 1-255 (default 16)
+    --poke <addr=value>
+                      set a byte of memory after loading the ROM, both in hex,
+                      e.g. 0300=FF; can be given more than once
+    --force           allow `--poke` below the load address
+    --record <file>   record the display to an animated GIF (needs the `record`
+                      feature)
+    --scale <n>       size in pixels of each display pixel in screenshots,
+                      recordings and the window (default 10)
+    --seed <n>        seed for the random number generator, 1-255 (default 255)
+    --render <mode>   `halfblock` (default) or `ascii` for terminals without
+                      good unicode block characters
+    --theme <name>    colours for the display: `plain` (default), `green`,
+                      `amber`, `white`, `inverted` or `octo`
+    --palette <colours>
+                      four comma-separated colours for unlit pixels, the first
+                      plane, the second plane and both, each RRGGBB in hex or a
+                      256 colour palette number, e.g. 000000,FFFFFF,FF0000,0000FF
+    --max-fps <n>     most times a second the display is drawn (default 60)
+    -h, --help        print this message";
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Args {
+    pub rom: PathBuf,
+    /// Instructions per second.
+    pub hz: u32,
+    /// Whether `--hz` was given, rather than `hz` being the default.
+    pub hz_given: bool,
+    /// Instructions per 60Hz frame, if they're executed in frames rather than at `hz`.
+    pub cycles_per_frame: Option<u32>,
+    pub mute: bool,
+    /// Ring the terminal bell rather than playing sound.
+    pub beep_bell: bool,
+    pub no_display: bool,
+    /// Draw the display in a window rather than the terminal.
+    pub window: bool,
+    /// Instructions to execute before exiting, if there's a limit.
+    pub max_cycles: Option<u64>,
+    pub disassemble: bool,
+    pub verify: bool,
+    pub bench: bool,
+    pub render: RenderMode,
+    pub theme: Theme,
+    pub debug: bool,
+    /// Instructions the debugger keeps snapshots of, to step back through.
+    pub rewind: usize,
+    pub trace: Option<PathBuf>,
+    pub dump_state: bool,
+    pub profile: bool,
+    /// Skip unknown opcodes instead of stopping with an error.
+    pub skip_unknown: bool,
+    /// The opcode at which to print a hash of the display, with `--test-hooks`.
+    pub test_hook: Option<u16>,
+    pub record_input: Option<PathBuf>,
+    pub replay: Option<PathBuf>,
+    pub record: Option<PathBuf>,
+    pub seed: Option<u8>,
+    /// Size of each display pixel in screenshots, recordings and the window.
+    pub scale: usize,
+    pub quirks: Quirks,
+    /// Whether a preset was given, by `--quirks` or `--accurate`.
+    pub quirks_given: bool,
+    pub detect_quirks: bool,
+    /// Quirks to turn on or off, by name, after choosing the preset.
+    pub quirk_overrides: Vec<(String, bool)>,
+    /// Bytes of memory to overwrite after loading the ROM, as addresses and values.
+    pub pokes: Vec<(u16, u8)>,
+    pub keys: KeyMap,
+    /// How long a key is held down for when the terminal can't report releases.
+    pub key_hold: Duration,
+    /// The most frames drawn per second.
+    pub max_fps: u32,
+}
+
+/// The outcome of parsing arguments when they don't describe a run of the emulator.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The user asked for the usage message.
+    Help,
+    Invalid(String),
+}
+
+impl Args {
+    /// Parse the arguments following the program name.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, Error> {
+        // Split `--option=value` into `--option value`.
+        let mut args = args.into_iter().flat_map(|arg| match arg.split_once('=') {
+            Some((flag, value)) if arg.starts_with("--") => vec![flag.to_owned(), value.to_owned()],
+            _ => vec![arg],
+        });
+        let mut rom = None;
+        let mut hz = None;
+        let mut frame_timing = false;
+        let mut cycles_per_frame = None;
+        let mut mute = false;
+        let mut beep_bell = false;
+        let mut no_display = false;
+        let mut window = false;
+        let mut max_cycles = None;
+        let mut disassemble = false;
+        let mut verify = false;
+        let mut bench = false;
+        let mut render = RenderMode::HalfBlock;
+        let mut theme = Theme::PLAIN;
+        let mut debug = false;
+        let mut rewind = DEFAULT_REWIND;
+        let mut trace = None;
+        let mut dump_state = false;
+        let mut profile = false;
+        let mut skip_unknown = false;
+        let mut test_hooks = false;
+        let mut hook_opcode = None;
+        let mut record_input = None;
+        let mut replay = None;
+        let mut record = None;
+        let mut seed = None;
+        let mut scale = 10;
+        let mut quirks = Quirks::default();
+        let mut quirks_given = false;
+        let mut detect_quirks = false;
+        let mut quirk_overrides = Vec::new();
+        let mut memory_size = None;
+        let mut load_address = None;
+        let mut stack_depth = None;
+        let mut byte_swap = false;
+        let mut pokes = Vec::new();
+        let mut force = false;
+        let mut keys = KeyMap::default();
+        let mut key_hold = DEFAULT_KEY_HOLD;
+        let mut max_fps = DEFAULT_MAX_FPS;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-h" | "--help" => return Err(Error::Help),
+                "--hz" => {
+                    let n = value(&mut args, &arg)?;
+                    if n == 0 {
+                        return Err(Error::Invalid("`--hz` must be positive".to_owned()));
+                    }
+                    hz = Some(n);
+                }
+                "--frame-timing" => frame_timing = true,
+                "--cycles-per-frame" => {
+                    let n = value(&mut args, &arg)?;
+                    if n == 0 {
+                        return Err(Error::Invalid(
+                            "`--cycles-per-frame` must be positive".to_owned(),
+                        ));
+                    }
+                    cycles_per_frame = Some(n);
+                }
+                "--mute" => mute = true,
+                "--beep-bell" => beep_bell = true,
+                "--no-display" => no_display = true,
+                "--window" => window = true,
+                "--max-cycles" => {
+                    let n = value(&mut args, &arg)?;
+                    if n == 0 {
+                        return Err(Error::Invalid("`--max-cycles` must be positive".to_owned()));
+                    }
+                    max_cycles = Some(n);
+                }
+                "--disassemble" => disassemble = true,
+                "--verify" => verify = true,
+                "--bench" => bench = true,
+                "--render" => render = value(&mut args, &arg)?,
+                "--theme" => theme = value(&mut args, &arg)?,
+                "--palette" => {
+                    theme = Theme::from_palette(&value::<String>(&mut args, &arg)?)
+                        .map_err(|e| Error::Invalid(format!("invalid `--palette`: {e}")))?;
+                }
+                "--debug" => debug = true,
+                "--rewind" => rewind = value(&mut args, &arg)?,
+                "--trace" => trace = Some(value(&mut args, &arg)?),
+                "--dump-state-on-exit" => dump_state = true,
+                "--profile" => profile = true,
+                "--skip-unknown" => skip_unknown = true,
+                "--test-hooks" => test_hooks = true,
+                "--hook-opcode" => {
+                    let opcode: String = value(&mut args, &arg)?;
+                    let opcode = u16::from_str_radix(&opcode, 16).map_err(|_| {
+                        Error::Invalid(format!("invalid value `{opcode}` for `--hook-opcode`"))
+                    })?;
+                    // A real instruction would be executed rather than caught.
+                    if mnemonic(opcode).is_some() {
+                        return Err(Error::Invalid(format!(
+                            "`--hook-opcode` {opcode:04X} is an instruction the emulator implements"
+                        )));
+                    }
+                    hook_opcode = Some(opcode);
+                }
+                "--record-input" => record_input = Some(value(&mut args, &arg)?),
+                "--replay" => replay = Some(value(&mut args, &arg)?),
+                "--scale" => {
+                    scale = value(&mut args, &arg)?;
+                    if scale == 0 {
+                        return Err(Error::Invalid("`--scale` must be positive".to_owned()));
+                    }
+                }
+                "--record" => record = Some(value(&mut args, &arg)?),
+                "--seed" => seed = Some(value(&mut args, &arg)?),
+                "--quirks" => {
+                    quirks = value(&mut args, &arg)?;
+                    quirks_given = true;
+                }
+                // Spelled out for users who want accuracy without knowing the preset's name.
+                "--accurate" => {
+                    quirks = Quirks::chip8();
+                    quirks_given = true;
+                }
+                "--detect-quirks" => detect_quirks = true,
+                "--quirk" => {
+                    let quirk: String = value(&mut args, &arg)?;
+                    let invalid = || Error::Invalid(format!("invalid `--quirk` `{quirk}`"));
+                    let (name, on) = match quirk.split_once('=') {
+                        Some((name, "on")) => (name, true),
+                        Some((name, "off")) => (name, false),
+                        Some(_) => return Err(invalid()),
+                        None => (quirk.as_str(), true),
+                    };
+                    if Quirks::default().switch_mut(name).is_none() {
+                        return Err(Error::Invalid(format!("unknown quirk `{name}`")));
+                    }
+                    quirk_overrides.push((name.to_owned(), on));
+                }
+                "--memory" => memory_size = Some(number(&mut args, &arg)?),
+                "--load-address" => load_address = Some(number(&mut args, &arg)?),
+                "--byte-swap" => byte_swap = true,
+                "--stack-depth" => {
+                    let n: u8 = value(&mut args, &arg)?;
+                    if n == 0 {
+                        return Err(Error::Invalid(
+                            "`--stack-depth` must be positive".to_owned(),
+                        ));
+                    }
+                    stack_depth = Some(n as usize);
+                }
+                "--poke" => {
+                    let poke: String = value(&mut args, &arg)?;
+                    let invalid = || Error::Invalid(format!("invalid `--poke` `{poke}`"));
+                    let (addr, value) = poke.split_once('=').ok_or_else(invalid)?;
+                    pokes.push((
+                        u16::from_str_radix(addr, 16).map_err(|_| invalid())?,
+                        u8::from_str_radix(value, 16).map_err(|_| invalid())?,
+                    ));
+                }
+                "--force" => force = true,
+                "--keys" => {
+                    keys = value::<String>(&mut args, &arg)?
+                        .parse()
+                        .map_err(|e| Error::Invalid(format!("invalid `--keys`: {e}")))?;
+                }
+                "--key-hold" => {
+                    let ms = value(&mut args, &arg)?;
+                    if ms == 0 {
+                        return Err(Error::Invalid("`--key-hold` must be positive".to_owned()));
+                    }
+                    key_hold = Duration::from_millis(ms);
+                }
+                "--max-fps" => {
+                    max_fps = value(&mut args, &arg)?;
+                    if max_fps == 0 {
+                        return Err(Error::Invalid("`--max-fps` must be positive".to_owned()));
+                    }
+                }
+                _ if arg.starts_with('-') && arg != "-" => {
+                    return Err(Error::Invalid(format!("unknown option `{arg}`")))
+                }
+                _ if rom.is_some() => {
+                    return Err(Error::Invalid(format!("unexpected argument `{arg}`")))
+                }
+                _ => rom = Some(PathBuf::from(arg)),
+            }
+        }
+        if debug && no_display {
+            return Err(Error::Invalid(
+                "`--debug` can't be used with `--no-display`".to_owned(),
+            ));
+        }
+        if window && (no_display || debug) {
+            return Err(Error::Invalid(
+                "`--window` can't be used with `--no-display` or `--debug`".to_owned(),
+            ));
+        }
+        if record_input.is_some() && replay.is_some() {
+            return Err(Error::Invalid(
+                "`--record-input` can't be used with `--replay`".to_owned(),
+            ));
+        }
+        if hook_opcode.is_some() && !test_hooks {
+            return Err(Error::Invalid(
+                "`--hook-opcode` needs `--test-hooks`".to_owned(),
+            ));
+        }
+        if frame_timing && cycles_per_frame.is_none() {
+            cycles_per_frame = Some(DEFAULT_CYCLES_PER_FRAME);
+        }
+        if hz.is_some() && cycles_per_frame.is_some() {
+            return Err(Error::Invalid(
+                "`--hz` can't be used with frame timing, use `--cycles-per-frame` instead"
+                    .to_owned(),
+            ));
+        }
+        // These override the preset whichever order they're given in.
+        for (name, on) in &quirk_overrides {
+            *quirks.switch_mut(name).expect("checked when parsed") = *on;
+        }
+        quirks.memory_size = memory_size.unwrap_or(quirks.memory_size);
+        quirks.load_address = load_address.unwrap_or(quirks.load_address);
+        quirks.stack_depth = stack_depth.unwrap_or(quirks.stack_depth);
+        quirks.byte_swap = byte_swap;
+        if quirks.memory_size > 0x10000 {
+            return Err(Error::Invalid(
+                "`--memory` can be at most 65536 bytes".to_owned(),
+            ));
+        }
+        if quirks.load_address < FONT_END {
+            return Err(Error::Invalid(format!(
+                "`--load-address` must be at least {FONT_END:#X}, after the fonts"
+            )));
+        }
+        if quirks.load_address >= quirks.memory_size {
+            return Err(Error::Invalid(
+                "`--load-address` must be within memory".to_owned(),
+            ));
+        }
+        for &(addr, _) in &pokes {
+            if addr as usize >= quirks.memory_size {
+                return Err(Error::Invalid(format!(
+                    "`--poke` address {addr:#06X} is outside memory"
+                )));
+            }
+            if (addr as usize) < quirks.load_address && !force {
+                return Err(Error::Invalid(format!(
+                    "`--poke` address {addr:#06X} is below the program, pass `--force` to allow it"
+                )));
+            }
+        }
+        Ok(Self {
+            rom: rom.ok_or_else(|| Error::Invalid("missing ROM path".to_owned()))?,
+            hz: hz.unwrap_or(DEFAULT_HZ),
+            hz_given: hz.is_some(),
+            cycles_per_frame,
+            mute,
+            beep_bell,
+            no_display,
+            window,
+            max_cycles,
+            disassemble,
+            verify,
+            bench,
+            render,
+            theme,
+            debug,
+            rewind,
+            trace,
+            dump_state,
+            profile,
+            skip_unknown,
+            test_hook: test_hooks.then_some(hook_opcode.unwrap_or(DEFAULT_HOOK_OPCODE)),
+            record_input,
+            replay,
+            record,
+            seed,
+            scale,
+            quirks,
+            quirks_given,
+            detect_quirks,
+            quirk_overrides,
+            pokes,
+            keys,
+            key_hold,
+            max_fps,
+        })
+    }
+
+    /// Switch to the quirks `preset`, keeping the load address, stack depth, byte swapping and
+    /// `--quirk` overrides given on the command line and at least as much memory as the preset
+    /// needs.
+    pub fn use_preset(&mut self, preset: Quirks) {
+        self.quirks = Quirks {
+            memory_size: self.quirks.memory_size.max(preset.memory_size),
+            load_address: self.quirks.load_address,
+            stack_depth: self.quirks.stack_depth,
+            byte_swap: self.quirks.byte_swap,
+            ..preset
+        };
+        for (name, on) in &self.quirk_overrides {
+            *self.quirks.switch_mut(name).expect("checked when parsed") = *on;
+        }
+    }
+}
+
+/// Parse the value following the option `flag`.
+fn value<T: FromStr>(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<T, Error> {
+    let value = args
+        .next()
+        .ok_or_else(|| Error::Invalid(format!("`{flag}` expects a value")))?;
+    value
+        .parse()
+        .map_err(|_| Error::Invalid(format!("invalid value `{value}` for `{flag}`")))
+}
+
+/// Parse the number following the option `flag`, which is hexadecimal if prefixed with `0x`.
+fn number(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<usize, Error> {
+    let value: String = value(args, flag)?;
+    match value.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => value.parse(),
+    }
+    .map_err(|_| Error::Invalid(format!("invalid value `{value}` for `{flag}`")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::Colour;
+
+    fn parse(args: &[&str]) -> Result<Args, Error> {
+        Args::parse(args.iter().map(|s| s.to_string()))
+    }
+
+    #[test]
+    fn rom_path() {
+        assert_eq!(parse(&["pong.ch8"]).unwrap().rom, PathBuf::from("pong.ch8"));
+        assert!(matches!(parse(&[]), Err(Error::Invalid(_))));
+        assert!(matches!(parse(&["a.ch8", "b.ch8"]), Err(Error::Invalid(_))));
+        assert_eq!(parse(&["--help"]), Err(Error::Help));
+        assert_eq!(parse(&["-"]).unwrap().rom, PathBuf::from("-"));
+    }
+
+    #[test]
+    fn hz() {
+        assert_eq!(parse(&["a.ch8"]).unwrap().hz, DEFAULT_HZ);
+        assert_eq!(parse(&["--hz", "1000", "a.ch8"]).unwrap().hz, 1000);
+        assert!(matches!(
+            parse(&["--hz", "0", "a.ch8"]),
+            Err(Error::Invalid(_))
+        ));
+        assert!(matches!(
+            parse(&["--hz", "-5", "a.ch8"]),
+            Err(Error::Invalid(_))
+        ));
+        assert!(matches!(parse(&["a.ch8", "--hz"]), Err(Error::Invalid(_))));
+    }
+
+    #[test]
+    fn frame_timing() {
+        assert_eq!(parse(&["a.ch8"]).unwrap().cycles_per_frame, None);
+        assert_eq!(
+            parse(&["--frame-timing", "a.ch8"])
+                .unwrap()
+                .cycles_per_frame,
+            Some(DEFAULT_CYCLES_PER_FRAME)
+        );
+        assert_eq!(
+            parse(&["--cycles-per-frame=20", "--frame-timing", "a.ch8"])
+                .unwrap()
+                .cycles_per_frame,
+            Some(20)
+        );
+        assert!(matches!(
+            parse(&["--cycles-per-frame", "0", "a.ch8"]),
+            Err(Error::Invalid(_))
+        ));
+        assert!(matches!(
+            parse(&["--frame-timing", "--hz", "1000", "a.ch8"]),
+            Err(Error::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn input_logs() {
+        let args = parse(&["--record-input=keys.log", "a.ch8"]).unwrap();
+        assert_eq!(args.record_input, Some(PathBuf::from("keys.log")));
+        assert_eq!(args.replay, None);
+        assert!(matches!(
+            parse(&["--record-input=a.log", "--replay=b.log", "a.ch8"]),
+            Err(Error::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn scale() {
+        assert_eq!(parse(&["a.ch8"]).unwrap().scale, 10);
+        assert_eq!(parse(&["--scale=4", "a.ch8"]).unwrap().scale, 4);
+        assert!(matches!(
+            parse(&["--scale", "0", "a.ch8"]),
+            Err(Error::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn window() {
+        assert!(!parse(&["a.ch8"]).unwrap().window);
+        assert!(parse(&["--window", "a.ch8"]).unwrap().window);
+        for args in [
+            ["--window", "--no-display", "a.ch8"],
+            ["--debug", "--window", "a.ch8"],
+        ] {
+            assert!(matches!(parse(&args), Err(Error::Invalid(_))));
+        }
+    }
+
+    #[test]
+    fn key_hold() {
+        assert_eq!(parse(&["a.ch8"]).unwrap().key_hold, DEFAULT_KEY_HOLD);
+        assert_eq!(
+            parse(&["--key-hold=250", "a.ch8"]).unwrap().key_hold,
+            Duration::from_millis(250)
+        );
+        assert!(matches!(
+            parse(&["--key-hold", "0", "a.ch8"]),
+            Err(Error::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn max_fps() {
+        assert_eq!(parse(&["a.ch8"]).unwrap().max_fps, DEFAULT_MAX_FPS);
+        assert_eq!(parse(&["--max-fps=144", "a.ch8"]).unwrap().max_fps, 144);
+        assert!(matches!(
+            parse(&["--max-fps", "0", "a.ch8"]),
+            Err(Error::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn max_cycles() {
+        assert_eq!(parse(&["a.ch8"]).unwrap().max_cycles, None);
+        assert_eq!(
+            parse(&["--max-cycles", "1000", "a.ch8"])
+                .unwrap()
+                .max_cycles,
+            Some(1000)
+        );
+        assert!(matches!(
+            parse(&["--max-cycles", "0", "a.ch8"]),
+            Err(Error::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn test_hooks() {
+        assert_eq!(parse(&["a.ch8"]).unwrap().test_hook, None);
+        assert_eq!(
+            parse(&["--test-hooks", "a.ch8"]).unwrap().test_hook,
+            Some(0x0F00)
+        );
+        assert_eq!(
+            parse(&["--test-hooks", "--hook-opcode", "0ABC", "a.ch8"])
+                .unwrap()
+                .test_hook,
+            Some(0x0ABC)
+        );
+        for args in [
+            &["--hook-opcode", "0ABC", "a.ch8"][..],
+            &["--test-hooks", "--hook-opcode", "00E0", "a.ch8"],
+            &["--test-hooks", "--hook-opcode", "hook", "a.ch8"],
+        ] {
+            assert!(matches!(parse(args), Err(Error::Invalid(_))), "{args:?}");
+        }
+    }
+
+    #[test]
+    fn quirks() {
+        assert_eq!(parse(&["a.ch8"]).unwrap().quirks, Quirks::chip8());
+        assert_eq!(
+            parse(&["--quirks=superchip", "a.ch8"]).unwrap().quirks,
+            Quirks::superchip()
+        );
+        assert!(matches!(
+            parse(&["--quirks", "chip48", "a.ch8"]),
+            Err(Error::Invalid(_))
+        ));
+        // Like `--quirks`, the last preset given wins, and the memory layout still applies.
+        let quirks = parse(&["--quirks=xochip", "--accurate", "--stack-depth=12", "a.ch8"])
+            .unwrap()
+            .quirks;
+        assert_eq!(
+            quirks,
+            Quirks {
+                stack_depth: 12,
+                ..Quirks::chip8()
+            }
+        );
+        assert_eq!(
+            parse(&["--accurate", "--quirks=superchip", "a.ch8"])
+                .unwrap()
+                .quirks,
+            Quirks::superchip()
+        );
+    }
+
+    #[test]
+    fn quirk_overrides() {
+        // Overrides apply to whichever preset is chosen, even one given after them.
+        let args = parse(&[
+            "--quirk=display-wait=off",
+            "--quirk",
+            "shift",
+            "--quirks=xochip",
+            "--quirk",
+            "shift=off",
+            "a.ch8",
+        ])
+        .unwrap();
+        assert_eq!(
+            args.quirks,
+            Quirks {
+                display_wait: false,
+                shift: false,
+                ..Quirks::xochip()
+            }
+        );
+        let quirks = parse(&["--accurate", "--quirk=vf-reset=off", "a.ch8"])
+            .unwrap()
+            .quirks;
+        assert_eq!(
+            quirks,
+            Quirks {
+                vf_reset: false,
+                ..Quirks::chip8()
+            }
+        );
+        for quirk in ["--quirk=vf-reset=maybe", "--quirk=memory-size", "--quirk="] {
+            assert!(matches!(parse(&[quirk, "a.ch8"]), Err(Error::Invalid(_))));
+        }
+    }
+
+    #[test]
+    fn use_preset() {
+        let mut args =
+            parse(&["--load-address=0x600", "--quirk=wrap-sprites=off", "a.ch8"]).unwrap();
+        args.use_preset(Quirks::xochip());
+        assert_eq!(
+            args.quirks,
+            Quirks {
+                load_address: 0x600,
+                wrap_sprites: false,
+                ..Quirks::xochip()
+            }
+        );
+        // The memory is only ever enlarged.
+        let mut args = parse(&["--memory=0x2000", "a.ch8"]).unwrap();
+        args.use_preset(Quirks::superchip());
+        assert_eq!(args.quirks.memory_size, 0x2000);
+    }
+
+    #[test]
+    fn memory_layout() {
+        let quirks = parse(&["--load-address", "0x600", "a.ch8"]).unwrap().quirks;
+        assert_eq!((quirks.memory_size, quirks.load_address), (0x1000, 0x600));
+        // The preset doesn't undo an earlier `--memory`.
+        let quirks = parse(&["--memory=8192", "--quirks=xochip", "a.ch8"])
+            .unwrap()
+            .quirks;
+        assert_eq!((quirks.memory_size, quirks.load_address), (0x2000, 0x200));
+        let quirks = parse(&["--stack-depth", "12", "a.ch8"]).unwrap().quirks;
+        assert_eq!(quirks.stack_depth, 12);
+        assert!(!quirks.byte_swap);
+        assert!(parse(&["--byte-swap", "a.ch8"]).unwrap().quirks.byte_swap);
+        for args in [
+            ["--memory", "0x10001", "a.ch8"],
+            ["--memory", "0x200", "a.ch8"],
+            ["--load-address", "0x100", "a.ch8"],
+            ["--load-address", "six", "a.ch8"],
+            ["--stack-depth", "0", "a.ch8"],
+            ["--stack-depth", "256", "a.ch8"],
+        ] {
+            assert!(matches!(parse(&args), Err(Error::Invalid(_))), "{args:?}");
+        }
+    }
+
+    #[test]
+    fn pokes() {
+        let args = parse(&["--poke", "0300=FF", "--poke=FFF=1", "a.ch8"]).unwrap();
+        assert_eq!(args.pokes, [(0x300, 0xFF), (0xFFF, 0x01)]);
+        let args = parse(&["--poke", "0100=00", "--force", "a.ch8"]).unwrap();
+        assert_eq!(args.pokes, [(0x100, 0x00)]);
+        for args in [
+            ["--poke", "0100=00", "a.ch8"],
+            ["--poke", "1000=00", "a.ch8"],
+            ["--poke", "0300", "a.ch8"],
+            ["--poke", "0300=100", "a.ch8"],
+        ] {
+            assert!(matches!(parse(&args), Err(Error::Invalid(_))), "{args:?}");
+        }
+    }
+
+    #[test]
+    fn render_mode() {
+        assert_eq!(parse(&["a.ch8"]).unwrap().render, RenderMode::HalfBlock);
+        assert_eq!(
+            parse(&["--render=ascii", "a.ch8"]).unwrap().render,
+            RenderMode::Ascii
+        );
+        assert_eq!(
+            parse(&["--render", "ascii", "a.ch8"]).unwrap().render,
+            RenderMode::Ascii
+        );
+        assert!(matches!(
+            parse(&["--render=sixel", "a.ch8"]),
+            Err(Error::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn theme() {
+        assert_eq!(parse(&["a.ch8"]).unwrap().theme, Theme::PLAIN);
+        assert_eq!(
+            parse(&["--theme=amber", "a.ch8"]).unwrap().theme,
+            Theme::AMBER
+        );
+        assert!(matches!(
+            parse(&["--theme", "purple", "a.ch8"]),
+            Err(Error::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn palette() {
+        assert_eq!(
+            parse(&["--palette", "#000000,FFFFFF, 196,21", "a.ch8"])
+                .unwrap()
+                .theme
+                .palette,
+            Some([
+                Colour::Rgb(0, 0, 0),
+                Colour::Rgb(255, 255, 255),
+                Colour::Ansi(196),
+                Colour::Ansi(21),
+            ])
+        );
+        // Whichever of `--theme` and `--palette` comes last wins.
+        assert_eq!(
+            parse(&["--palette", "0,1,2,3", "--theme", "octo", "a.ch8"])
+                .unwrap()
+                .theme,
+            Theme::OCTO
+        );
+        for palette in ["0,1,2", "0,1,2,3,4", "0,1,2,256", "0,1,2,GGGGGG"] {
+            assert!(matches!(
+                parse(&["--palette", palette, "a.ch8"]),
+                Err(Error::Invalid(_))
+            ));
+        }
+    }
+}
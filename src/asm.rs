@@ -0,0 +1,256 @@
+//! A minimal assembler, the inverse of [`disasm`](crate::disasm), for writing short programs such
+//! as test snippets without assembling them by hand.
+//!
+//! Each line holds one instruction in the notation the disassembler prints, with anything after a
+//! `;` ignored. Mnemonics and operands are case-insensitive, and numbers are decimal or
+//! hexadecimal when prefixed with `#` or `0x`. There are no labels, so jumps take addresses.
+//!
+//! ```
+//! let rom = chip8::asm::assemble("LD V0, 5\nDRW V0, V0, 1 ; draw at (5, 5)").unwrap();
+//! assert_eq!(rom, [0x60, 0x05, 0xD0, 0x01]);
+//! ```
+//!
+//! `LD I, addr` assembles to `ANNN` for addresses up to `#FFF`, and to XO-CHIP's four byte
+//! `F000 NNNN` above that. `DB` and `DW` insert bytes and words of data.
+
+use alloc::{borrow::ToOwned, format, string::String, vec::Vec};
+use core::{fmt, str::FromStr};
+
+/// A line which couldn't be assembled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    /// Numbered from 1.
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl core::error::Error for AsmError {}
+
+/// Assemble `src` into a ROM, ready for [`Chip8::load_rom`](crate::Chip8::load_rom).
+pub fn assemble(src: &str) -> Result<Vec<u8>, AsmError> {
+    let mut rom = Vec::new();
+    for (i, line) in src.lines().enumerate() {
+        let code = line.split(';').next().unwrap_or_default().trim();
+        if code.is_empty() {
+            continue;
+        }
+        let bytes = instruction(code).map_err(|message| AsmError {
+            line: i + 1,
+            message,
+        })?;
+        rom.extend(bytes);
+    }
+    Ok(rom)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operand {
+    V(u16),
+    Number(u32),
+    I,
+    Dt,
+    St,
+    K,
+    F,
+    Hf,
+    B,
+    /// `[I]`, the memory I points at.
+    AtI,
+    R,
+    Long,
+}
+
+impl FromStr for Operand {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let upper = s.to_ascii_uppercase();
+        Ok(match upper.as_str() {
+            "I" => Self::I,
+            "DT" => Self::Dt,
+            "ST" => Self::St,
+            "K" => Self::K,
+            "F" => Self::F,
+            "HF" => Self::Hf,
+            "B" => Self::B,
+            "[I]" => Self::AtI,
+            "R" => Self::R,
+            "LONG" => Self::Long,
+            _ => {
+                let register = upper
+                    .strip_prefix('V')
+                    .filter(|x| x.len() == 1)
+                    .and_then(|x| u16::from_str_radix(x, 16).ok());
+                let number = || match upper.strip_prefix('#').or(upper.strip_prefix("0X")) {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                    None => upper.parse().ok(),
+                };
+                match register {
+                    Some(x) => Self::V(x),
+                    None => Self::Number(number().ok_or_else(|| format!("invalid operand `{s}`"))?),
+                }
+            }
+        })
+    }
+}
+
+/// `n` as a `bits` wide field of an instruction.
+fn field(n: u32, bits: u32) -> Result<u16, String> {
+    if n >> bits == 0 {
+        Ok(n as u16)
+    } else {
+        Err(format!("{n:#X} doesn't fit in {bits} bits"))
+    }
+}
+
+/// Assemble a single instruction, without any comment.
+fn instruction(code: &str) -> Result<Vec<u8>, String> {
+    use Operand::*;
+
+    let (mnemonic, operands) = code.split_once(char::is_whitespace).unwrap_or((code, ""));
+    let mnemonic = mnemonic.to_ascii_uppercase();
+    let operands = match operands.trim() {
+        "" => Vec::new(),
+        operands => operands
+            .split(',')
+            .map(|operand| operand.trim().parse())
+            .collect::<Result<Vec<Operand>, _>>()?,
+    };
+    let x = |x: &u16| x << 8;
+    let xy = |x: &u16, y: &u16| x << 8 | y << 4;
+
+    let word = match (mnemonic.as_str(), operands.as_slice()) {
+        ("CLS", []) => 0x00E0,
+        ("RET", []) => 0x00EE,
+        ("SCD", [Number(n)]) => 0x00C0 | field(*n, 4)?,
+        ("SCR", []) => 0x00FB,
+        ("SCL", []) => 0x00FC,
+        ("EXIT", []) => 0x00FD,
+        ("LOW", []) => 0x00FE,
+        ("HIGH", []) => 0x00FF,
+        ("JP", [Number(nnn)]) => 0x1000 | field(*nnn, 12)?,
+        ("CALL", [Number(nnn)]) => 0x2000 | field(*nnn, 12)?,
+        ("SE", [V(vx), Number(nn)]) => 0x3000 | x(vx) | field(*nn, 8)?,
+        ("SNE", [V(vx), Number(nn)]) => 0x4000 | x(vx) | field(*nn, 8)?,
+        ("SE", [V(vx), V(vy)]) => 0x5000 | xy(vx, vy),
+        ("LD", [V(vx), Number(nn)]) => 0x6000 | x(vx) | field(*nn, 8)?,
+        ("ADD", [V(vx), Number(nn)]) => 0x7000 | x(vx) | field(*nn, 8)?,
+        ("LD", [V(vx), V(vy)]) => 0x8000 | xy(vx, vy),
+        ("OR", [V(vx), V(vy)]) => 0x8001 | xy(vx, vy),
+        ("AND", [V(vx), V(vy)]) => 0x8002 | xy(vx, vy),
+        ("XOR", [V(vx), V(vy)]) => 0x8003 | xy(vx, vy),
+        ("ADD", [V(vx), V(vy)]) => 0x8004 | xy(vx, vy),
+        ("SUB", [V(vx), V(vy)]) => 0x8005 | xy(vx, vy),
+        ("SHR", [V(vx), V(vy)]) => 0x8006 | xy(vx, vy),
+        ("SUBN", [V(vx), V(vy)]) => 0x8007 | xy(vx, vy),
+        ("SHL", [V(vx), V(vy)]) => 0x800E | xy(vx, vy),
+        ("SNE", [V(vx), V(vy)]) => 0x9000 | xy(vx, vy),
+        ("LD", [I, Number(nnn)]) if *nnn <= 0xFFF => 0xA000 | *nnn as u16,
+        ("LD", [I, Number(nnnn)]) => {
+            return Ok([0xF0, 0x00]
+                .into_iter()
+                .chain(field(*nnnn, 16)?.to_be_bytes())
+                .collect())
+        }
+        ("LD", [I, Long]) => 0xF000,
+        ("JP", [V(0), Number(nnn)]) => 0xB000 | field(*nnn, 12)?,
+        ("RND", [V(vx), Number(nn)]) => 0xC000 | x(vx) | field(*nn, 8)?,
+        ("DRW", [V(vx), V(vy), Number(n)]) => 0xD000 | xy(vx, vy) | field(*n, 4)?,
+        ("SKP", [V(vx)]) => 0xE09E | x(vx),
+        ("SKNP", [V(vx)]) => 0xE0A1 | x(vx),
+        ("PLANE", [Number(n)]) => 0xF001 | field(*n, 4)? << 8,
+        ("AUDIO", []) => 0xF002,
+        ("LD", [V(vx), Dt]) => 0xF007 | x(vx),
+        ("LD", [V(vx), K]) => 0xF00A | x(vx),
+        ("LD", [Dt, V(vx)]) => 0xF015 | x(vx),
+        ("LD", [St, V(vx)]) => 0xF018 | x(vx),
+        ("ADD", [I, V(vx)]) => 0xF01E | x(vx),
+        ("LD", [F, V(vx)]) => 0xF029 | x(vx),
+        ("LD", [Hf, V(vx)]) => 0xF030 | x(vx),
+        ("LD", [B, V(vx)]) => 0xF033 | x(vx),
+        ("PITCH", [V(vx)]) => 0xF03A | x(vx),
+        ("LD", [AtI, V(vx)]) => 0xF055 | x(vx),
+        ("LD", [V(vx), AtI]) => 0xF065 | x(vx),
+        ("LD", [R, V(vx)]) => 0xF075 | x(vx),
+        ("LD", [V(vx), R]) => 0xF085 | x(vx),
+        ("DB", [_, ..]) => {
+            return operands
+                .iter()
+                .map(|operand| match operand {
+                    Number(n) => field(*n, 8).map(|n| n as u8),
+                    _ => Err("`DB` takes numbers".to_owned()),
+                })
+                .collect()
+        }
+        ("DW", [Number(n)]) => field(*n, 16)?,
+        _ => return Err(format!("invalid instruction `{code}`")),
+    };
+    Ok(word.to_be_bytes().to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disasm::{disassemble, mnemonic};
+
+    #[test]
+    fn inverse_of_disassembler() {
+        // Some opcodes ignore their low bits, such as `5XY0`, so compare the mnemonics.
+        for opcode in 0..=u16::MAX {
+            if let Some(text) = mnemonic(opcode) {
+                let rom = assemble(&text).unwrap();
+                assert_eq!(rom.len(), 2, "{text}");
+                assert_eq!(mnemonic(u16::from_be_bytes([rom[0], rom[1]])), Some(text));
+            }
+        }
+        let rom = [
+            0x00, 0xE0, 0xA2, 0x2A, 0xD0, 0x1F, 0xF0, 0x00, 0x12, 0x34, 0xFF, 0xFF, 0x12,
+        ];
+        let listing: Vec<String> = disassemble(&rom, 0x200)
+            .into_iter()
+            .map(|(_, _, mnemonic)| mnemonic)
+            .collect();
+        assert_eq!(assemble(&listing.join("\n")), Ok(rom.to_vec()));
+    }
+
+    #[test]
+    fn syntax() {
+        let src = "
+            ; Comments and blank lines are skipped.
+            ld v0, 0x0A   ; lower case
+            LD VF, 255
+            DB 1, 2, #3
+        ";
+        assert_eq!(assemble(src), Ok(vec![0x60, 0x0A, 0x6F, 0xFF, 1, 2, 3]));
+    }
+
+    #[test]
+    fn errors() {
+        let error = |message: &str| AsmError {
+            line: 2,
+            message: message.to_owned(),
+        };
+        assert_eq!(
+            assemble("CLS\nLD V0, V1, V2"),
+            Err(error("invalid instruction `LD V0, V1, V2`"))
+        );
+        assert_eq!(
+            assemble("CLS\nJP #1000"),
+            Err(error("0x1000 doesn't fit in 12 bits"))
+        );
+        assert_eq!(
+            assemble("CLS\nLD V0, five"),
+            Err(error("invalid operand `five`"))
+        );
+        assert_eq!(
+            assemble("CLS\nLD VG, 1").unwrap_err().to_string(),
+            "line 2: invalid operand `VG`"
+        );
+    }
+}
@@ -0,0 +1,206 @@
+//! Sound output.
+//!
+//! To stick to the standard library we don't talk to the sound card ourselves, but stream raw PCM
+//! to whichever of `aplay` (ALSA) or `pacat` (PulseAudio) is installed. Without either, the
+//! terminal bell can stand in for the beep.
+
+use std::{
+    io::{self, Write},
+    process::{Child, Command, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use chip8::AudioPattern;
+
+/// Frequency of the beep.
+pub const TONE_HZ: u64 = 440;
+const SAMPLE_RATE: u64 = 22050;
+/// How far ahead of real time we let the player's buffer run. This bounds the latency between
+/// the sound timer changing and the beep starting or stopping.
+const LEAD: Duration = Duration::from_millis(50);
+
+const PLAYERS: &[(&str, &[&str])] = &[
+    (
+        "aplay",
+        &[
+            "-q",
+            "-t",
+            "raw",
+            "-f",
+            "U8",
+            "-c",
+            "1",
+            "-r",
+            "22050",
+            "--buffer-time=50000",
+        ],
+    ),
+    (
+        "pacat",
+        &[
+            "--raw",
+            "--format=u8",
+            "--channels=1",
+            "--rate=22050",
+            "--latency-msec=50",
+        ],
+    ),
+];
+
+/// Plays a square wave, or an XO-CHIP audio pattern, while enabled and not silenced. The player
+/// process is killed when this is dropped.
+pub struct Beeper {
+    playing: Arc<AtomicBool>,
+    silenced: Arc<AtomicBool>,
+    pattern: Arc<Mutex<Option<AudioPattern>>>,
+    player: Child,
+}
+
+impl Beeper {
+    pub fn spawn() -> io::Result<Self> {
+        let mut player = PLAYERS
+            .iter()
+            .find_map(|(program, args)| {
+                Command::new(program)
+                    .args(*args)
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null())
+                    .spawn()
+                    .ok()
+            })
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no `aplay` or `pacat`"))?;
+        let mut stdin = player.stdin.take().expect("stdin is piped");
+
+        let playing = Arc::new(AtomicBool::new(false));
+        let pattern = Arc::new(Mutex::new(None::<AudioPattern>));
+        let silenced = Arc::new(AtomicBool::new(false));
+        let thread_playing = Arc::clone(&playing);
+        let thread_silenced = Arc::clone(&silenced);
+        let thread_pattern = Arc::clone(&pattern);
+        thread::spawn(move || {
+            let start = Instant::now();
+            let mut written = 0;
+            // How far through the pattern we are, in pattern samples.
+            let mut position = 0.0;
+            let mut chunk = [0; SAMPLE_RATE as usize / 100];
+            loop {
+                let ahead = Duration::from_secs_f64(written as f64 / SAMPLE_RATE as f64)
+                    .saturating_sub(start.elapsed());
+                if ahead > LEAD {
+                    thread::sleep(ahead - LEAD);
+                }
+
+                let on = thread_playing.load(Ordering::Relaxed)
+                    && !thread_silenced.load(Ordering::Relaxed);
+                let pattern = *thread_pattern.lock().expect("no panics holding lock");
+                for sample in &mut chunk {
+                    let high = match pattern {
+                        Some(pattern) => {
+                            position = (position + pattern.rate() / SAMPLE_RATE as f64) % 128.0;
+                            pattern.sample(position as usize)
+                        }
+                        None => (written * 2 * TONE_HZ / SAMPLE_RATE).is_multiple_of(2),
+                    };
+                    *sample = match (on, high) {
+                        (false, _) => 0x80,
+                        (true, true) => 0xA0,
+                        (true, false) => 0x60,
+                    };
+                    written += 1;
+                }
+                // The player has gone away, either by `Beeper` being dropped or because there's
+                // no sound device.
+                if stdin.write_all(&chunk).is_err() {
+                    return;
+                }
+            }
+        });
+
+        Ok(Self {
+            playing,
+            silenced,
+            pattern,
+            player,
+        })
+    }
+
+    /// A hook for [`chip8::Chip8::on_sound`], which starts and stops the beep as soon as the sound
+    /// timer changes.
+    pub fn sound_hook(&self) -> impl FnMut(bool) + Send + 'static {
+        let playing = Arc::clone(&self.playing);
+        move |on| playing.store(on, Ordering::Relaxed)
+    }
+
+    /// Keep quiet, e.g. while paused, even if the sound timer is running.
+    pub fn set_silenced(&self, silenced: bool) {
+        self.silenced.store(silenced, Ordering::Relaxed);
+    }
+
+    /// Play `pattern` rather than the square wave, or the square wave again for `None`.
+    pub fn set_pattern(&self, pattern: Option<AudioPattern>) {
+        *self.pattern.lock().expect("no panics holding lock") = pattern;
+    }
+}
+
+impl Drop for Beeper {
+    fn drop(&mut self) {
+        drop(self.player.kill());
+        drop(self.player.wait());
+    }
+}
+
+/// The shortest time between terminal bells, so that games beeping in quick succession don't
+/// ring it continuously.
+const BELL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Decides when to ring the terminal bell: once when each beep starts, however long it lasts.
+#[derive(Debug, Default)]
+struct Bell {
+    rung: Option<Instant>,
+}
+
+impl Bell {
+    /// Whether to ring the bell when the sound timer turns `on` or off at `now`.
+    fn ring(&mut self, on: bool, now: Instant) -> bool {
+        let ring = on && self.rung.is_none_or(|rung| now - rung >= BELL_INTERVAL);
+        if ring {
+            self.rung = Some(now);
+        }
+        ring
+    }
+}
+
+/// A hook for [`chip8::Chip8::on_sound`] which rings the terminal bell in place of the beep, for
+/// when there's no sound device, e.g. over SSH.
+pub fn bell_hook() -> impl FnMut(bool) + Send + 'static {
+    let mut bell = Bell::default();
+    move |on| {
+        if bell.ring(on, Instant::now()) {
+            let mut stdout = io::stdout().lock();
+            drop(stdout.write_all(b"\x07"));
+            drop(stdout.flush());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bell() {
+        let mut bell = Bell::default();
+        let start = Instant::now();
+        assert!(bell.ring(true, start));
+        assert!(!bell.ring(false, start + Duration::from_secs(1)));
+        // Beeps too soon after the last bell are skipped.
+        assert!(!bell.ring(true, start + BELL_INTERVAL / 2));
+        assert!(bell.ring(true, start + BELL_INTERVAL));
+    }
+}
@@ -0,0 +1,53 @@
+//! Measuring how fast the interpreter executes instructions.
+
+use std::time::{Duration, Instant};
+
+use chip8::{Chip8, Chip8Error, StepOutcome};
+
+/// How long `--bench` runs the ROM for.
+pub const BENCH_TIME: Duration = Duration::from_secs(5);
+
+/// Checking the time is slow compared to an instruction, so it's only checked this often.
+const BATCH: u64 = 10_000;
+
+/// Execute instructions on `chip8` as fast as possible for at least `time`, returning how many
+/// were executed and how long it actually took. Programs which finish are restarted, so that short
+/// ROMs can be measured too.
+pub fn run(chip8: &mut Chip8, time: Duration) -> Result<(u64, Duration), Chip8Error> {
+    let start = Instant::now();
+    let mut steps = 0;
+    loop {
+        for _ in 0..BATCH {
+            match chip8.step()? {
+                StepOutcome::Halt => chip8.reset(),
+                // Frames pass instantly when running as fast as possible.
+                StepOutcome::WaitingForFrame => chip8.tick_timers(),
+                _ => {}
+            }
+        }
+        steps += BATCH;
+        let elapsed = start.elapsed();
+        if elapsed >= time {
+            return Ok((steps, elapsed));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chip8::Quirks;
+
+    #[test]
+    fn restarts_finished_programs() {
+        let mut chip8 = Chip8::new(Quirks::superchip());
+        // LD V0, #01; EXIT
+        chip8.load_rom(&[0x60, 0x01, 0x00, 0xFD]).unwrap();
+        let (steps, elapsed) = run(&mut chip8, Duration::ZERO).unwrap();
+        assert_eq!(steps, BATCH);
+        assert!(elapsed > Duration::ZERO);
+
+        chip8.load_rom(&[0xFF, 0xFF]).unwrap();
+        assert!(run(&mut chip8, Duration::ZERO).is_err());
+    }
+}
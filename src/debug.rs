@@ -0,0 +1,543 @@
+//! Interactive step debugger, shown in the terminal below the display.
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fmt::Write as _,
+    io::{self, Write},
+    mem,
+};
+
+use chip8::{disasm, Chip8, Warning};
+
+const HELP: &str = "commands: <enter>/s step, r step back, c continue, b [addr] list/toggle \
+                    breakpoints, w [addr] list/toggle watchpoints, x addr [len] dump memory";
+
+/// Instructions which can be stepped back through unless configured otherwise.
+pub const DEFAULT_REWIND: usize = 64;
+
+/// The opcode caught by `--test-hooks` unless configured otherwise. It's a `0NNN` machine code
+/// routine, which no interpreter runs.
+pub const DEFAULT_HOOK_OPCODE: u16 = 0x0F00;
+
+pub struct Debugger {
+    /// Terminal row to draw from.
+    row: usize,
+    paused: bool,
+    /// Whether to execute the next instruction while paused.
+    step: bool,
+    /// The command being typed.
+    line: String,
+    /// Feedback from the last command.
+    message: String,
+    breakpoints: HashSet<u16>,
+    /// Watched memory addresses, and the value they had when last checked.
+    watchpoints: HashMap<u16, u8>,
+    /// Don't break on the first instruction after continuing, so we can continue from a
+    /// breakpoint.
+    resuming: bool,
+    /// Snapshots from before each of the last `rewind` instructions executed, oldest first.
+    history: VecDeque<Vec<u8>>,
+    /// The snapshot from before the instruction being executed, which joins `history` once it's
+    /// done. Buffers are passed between this and `history` rather than allocated for every step.
+    snapshot: Vec<u8>,
+    rewind: usize,
+}
+
+impl Debugger {
+    /// Start a debugger which is paused before the first instruction, and can step back through
+    /// the last `rewind` instructions.
+    pub fn new(row: usize, rewind: usize) -> Self {
+        Self {
+            row,
+            paused: true,
+            step: false,
+            line: String::new(),
+            message: HELP.to_owned(),
+            breakpoints: HashSet::new(),
+            watchpoints: HashMap::new(),
+            resuming: false,
+            history: VecDeque::with_capacity(rewind),
+            snapshot: Vec::new(),
+            rewind,
+        }
+    }
+
+    /// Whether execution is paused, only running instructions when asked to step.
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Whether the user asked to execute an instruction while paused.
+    pub fn take_step(&mut self) -> bool {
+        mem::take(&mut self.step)
+    }
+
+    /// Check whether the instruction about to be executed has a breakpoint, pausing if so.
+    pub fn hit_breakpoint(&mut self, chip8: &Chip8) -> bool {
+        if mem::take(&mut self.resuming) || !self.breakpoints.contains(&chip8.pc()) {
+            return false;
+        }
+        self.paused = true;
+        self.message = format!("breakpoint at {:04X}", chip8.pc());
+        true
+    }
+
+    /// Check whether the instruction at `pc`, which has just been executed, changed any watched
+    /// memory, pausing if so.
+    pub fn check_watchpoints(&mut self, chip8: &Chip8, pc: u16) {
+        let mut changed: Vec<_> = self
+            .watchpoints
+            .iter_mut()
+            .filter_map(|(&addr, old)| {
+                let new = chip8.memory()[addr as usize];
+                (new != *old).then(|| (addr, mem::replace(old, new), new))
+            })
+            .collect();
+        if changed.is_empty() {
+            return;
+        }
+        changed.sort();
+        self.paused = true;
+        self.message = format!("{pc:04X} changed");
+        for (addr, old, new) in changed {
+            write!(self.message, " {addr:04X} from {old:02X} to {new:02X}").unwrap();
+        }
+    }
+
+    /// Take a snapshot of `chip8` before executing an instruction, so that [`Debugger::record`]
+    /// can remember it afterwards. Does nothing if there's no stepping back.
+    pub fn snapshot(&mut self, chip8: &Chip8) {
+        if self.rewind != 0 {
+            chip8.save_state_into(&mut self.snapshot);
+        }
+    }
+
+    /// Remember the last snapshot, from before the instruction just executed, so that it can be
+    /// stepped back to.
+    pub fn record(&mut self) {
+        if self.rewind == 0 {
+            return;
+        }
+        // Reuse the oldest snapshot's buffer for the next one once the history is full.
+        let spare = if self.history.len() == self.rewind {
+            self.history.pop_front().unwrap_or_default()
+        } else {
+            Vec::new()
+        };
+        self.history
+            .push_back(mem::replace(&mut self.snapshot, spare));
+    }
+
+    /// Pause to report a warning raised by the last instruction.
+    pub fn warn(&mut self, warning: Warning) {
+        self.paused = true;
+        self.message = format!("warning: {warning}");
+    }
+
+    /// Handle a character typed by the user. Returns whether the machine was changed, by stepping
+    /// back, so that the display needs drawing again.
+    pub fn input(&mut self, c: char, chip8: &mut Chip8) -> bool {
+        if !self.paused {
+            return false;
+        }
+        let mut changed = false;
+        match c {
+            '\n' => {
+                let line = mem::take(&mut self.line);
+                changed = self.command(line.trim(), chip8);
+            }
+            '\x7F' | '\x08' => drop(self.line.pop()),
+            c if !c.is_control() => self.line.push(c),
+            _ => {}
+        }
+        self.show(chip8);
+        changed
+    }
+
+    /// Run `command`, returning whether it changed the machine.
+    fn command(&mut self, command: &str, chip8: &mut Chip8) -> bool {
+        self.message.clear();
+        let mut words = command.split_whitespace();
+        match (words.next(), words.next(), words.next()) {
+            (None | Some("s"), None, None) => self.step = true,
+            (Some("r"), None, None) => match self.history.pop_back() {
+                Some(state) => {
+                    chip8
+                        .load_state(&state)
+                        .expect("snapshots are from this machine");
+                    // The watched bytes may have changed back.
+                    for (&addr, value) in &mut self.watchpoints {
+                        *value = chip8.memory()[addr as usize];
+                    }
+                    self.snapshot = state;
+                    self.message = format!("stepped back to {:04X}", chip8.pc());
+                    return true;
+                }
+                None => self.message = "can't step back any further".to_owned(),
+            },
+            (Some("c"), None, None) => {
+                self.paused = false;
+                self.resuming = true;
+            }
+            (Some("b"), None, None) => {
+                let mut breakpoints: Vec<_> = self.breakpoints.iter().collect();
+                breakpoints.sort();
+                self.message = "breakpoints:".to_owned();
+                for addr in breakpoints {
+                    write!(self.message, " {addr:04X}").unwrap();
+                }
+            }
+            (Some("b"), Some(addr), None) => match parse_addr(addr) {
+                Some(addr) if self.breakpoints.remove(&addr) => {
+                    self.message = format!("removed breakpoint at {addr:04X}");
+                }
+                Some(addr) => {
+                    self.breakpoints.insert(addr);
+                    self.message = format!("added breakpoint at {addr:04X}");
+                }
+                None => self.message = format!("invalid address `{addr}`"),
+            },
+            (Some("w"), None, None) => {
+                let mut watchpoints: Vec<_> = self.watchpoints.iter().collect();
+                watchpoints.sort();
+                self.message = "watchpoints:".to_owned();
+                for (addr, value) in watchpoints {
+                    write!(self.message, " {addr:04X}={value:02X}").unwrap();
+                }
+            }
+            (Some("w"), Some(addr), None) => match parse_addr(addr) {
+                Some(addr) if self.watchpoints.remove(&addr).is_some() => {
+                    self.message = format!("removed watchpoint at {addr:04X}");
+                }
+                Some(addr) if (addr as usize) < chip8.memory().len() => {
+                    let value = chip8.memory()[addr as usize];
+                    self.watchpoints.insert(addr, value);
+                    self.message = format!("added watchpoint at {addr:04X}={value:02X}");
+                }
+                _ => self.message = format!("invalid address `{addr}`"),
+            },
+            (Some("x"), Some(addr), len) => {
+                // 16 bytes, one line, unless told otherwise.
+                let len = len.map_or(Some(0x10), parse_addr);
+                self.message = match (parse_addr(addr), len) {
+                    (Some(addr), Some(len)) => hexdump(chip8.memory(), addr, len),
+                    (None, _) => format!("invalid address `{addr}`"),
+                    (_, None) => format!("invalid length in `{command}`"),
+                };
+            }
+            _ => self.message = format!("unknown command `{command}`; {HELP}"),
+        }
+        false
+    }
+
+    /// Print the machine state and the command prompt, or clear them when not paused.
+    pub fn show(&self, chip8: &Chip8) {
+        let mut out = format!("\x1B[{};1H\x1B[J", self.row);
+        if self.paused {
+            let pc = chip8.pc() as usize;
+            match chip8.memory().get(pc..pc + 2) {
+                Some(&[hi, lo]) => {
+                    let opcode = u16::from_be_bytes([hi, lo]);
+                    let mnemonic = disasm::mnemonic(opcode).unwrap_or_else(|| "???".to_owned());
+                    writeln!(out, "{pc:04X}  {opcode:04X}  {mnemonic}").unwrap();
+                }
+                _ => writeln!(out, "{pc:04X}  out of bounds").unwrap(),
+            }
+            writeln!(out, "{}", registers(chip8)).unwrap();
+            write!(out, "stack:").unwrap();
+            for addr in chip8.stack() {
+                write!(out, " {addr:04X}").unwrap();
+            }
+            writeln!(out).unwrap();
+            writeln!(out, "{}", self.message).unwrap();
+            write!(out, "> {}", self.line).unwrap();
+        }
+
+        let mut stdout = io::stdout().lock();
+        drop(stdout.write_all(out.as_bytes()));
+        drop(stdout.flush());
+    }
+}
+
+/// Format `len` bytes of `mem` from `start` as a classic hex dump, 16 bytes to a line with the
+/// printable ones shown as ASCII alongside. A range running past the end of `mem` is cut short,
+/// saying so on the last line.
+pub fn hexdump(mem: &[u8], start: u16, len: u16) -> String {
+    let start = (start as usize).min(mem.len());
+    let end = (start + len as usize).min(mem.len());
+    let mut lines = Vec::new();
+    for (i, bytes) in mem[start..end].chunks(16).enumerate() {
+        let mut line = format!("{:04X} ", start + 16 * i);
+        for (j, byte) in bytes.iter().enumerate() {
+            // An extra space halfway along.
+            let gap = if j == 8 { "  " } else { " " };
+            write!(line, "{gap}{byte:02X}").unwrap();
+        }
+        let padding = 3 * (16 - bytes.len()) + (bytes.len() <= 8) as usize;
+        let ascii: String = bytes
+            .iter()
+            .map(|&b| {
+                if b.is_ascii_graphic() || b == b' ' {
+                    b as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        write!(line, "{:padding$}  |{ascii}|", "").unwrap();
+        lines.push(line);
+    }
+    if end - start < len as usize {
+        lines.push(format!(
+            "(truncated at the end of memory, {:04X})",
+            mem.len()
+        ));
+    }
+    lines.join("\n")
+}
+
+/// Parse a hexadecimal address, optionally prefixed with `0x` or `#`.
+fn parse_addr(s: &str) -> Option<u16> {
+    let s = s
+        .strip_prefix("0x")
+        .or_else(|| s.strip_prefix('#'))
+        .unwrap_or(s);
+    u16::from_str_radix(s, 16).ok()
+}
+
+/// Format the registers compactly on one line, e.g. `V0=00 V1=1F ... I=0300 PC=020A SP=2`.
+pub fn registers(chip8: &Chip8) -> String {
+    let mut out = String::new();
+    for (i, v) in chip8.registers().iter().enumerate() {
+        write!(out, "V{i:X}={v:02X} ").unwrap();
+    }
+    write!(
+        out,
+        "I={:04X} PC={:04X} SP={} DT={:02X} ST={:02X}",
+        chip8.index(),
+        chip8.pc(),
+        chip8.stack().len(),
+        chip8.delay_timer(),
+        chip8.sound_timer(),
+    )
+    .unwrap();
+    out
+}
+
+/// The number of lines in [`overlay`].
+pub const OVERLAY_ROWS: usize = 2;
+
+/// The registers and timers over two short lines, to show below the display while running.
+pub fn overlay(chip8: &Chip8) -> String {
+    let mut out = format!(
+        "PC={:04X} I={:04X} SP={} DT={:02X} ST={:02X}\nV0-VF",
+        chip8.pc(),
+        chip8.index(),
+        chip8.stack().len(),
+        chip8.delay_timer(),
+        chip8.sound_timer(),
+    );
+    for v in chip8.registers() {
+        write!(out, " {v:02X}").unwrap();
+    }
+    out
+}
+
+/// Format the registers, timers and stack as one `KEY=value` line each, for scripts to parse. Values
+/// are hexadecimal, and the stack is listed outermost first, e.g. `STACK=0202 0310`.
+pub fn dump(chip8: &Chip8) -> String {
+    let mut out = String::new();
+    writeln!(out, "PC={:04X}", chip8.pc()).unwrap();
+    writeln!(out, "I={:04X}", chip8.index()).unwrap();
+    for (i, v) in chip8.registers().iter().enumerate() {
+        writeln!(out, "V{i:X}={v:02X}").unwrap();
+    }
+    writeln!(out, "DT={:02X}", chip8.delay_timer()).unwrap();
+    writeln!(out, "ST={:02X}", chip8.sound_timer()).unwrap();
+    let stack: Vec<_> = chip8.stack().iter().map(|a| format!("{a:04X}")).collect();
+    writeln!(out, "STACK={}", stack.join(" ")).unwrap();
+    out
+}
+
+/// The line printed for a test hook at `pc`, with the display's size and hash for a test harness
+/// to compare, e.g. `checkpoint PC=0206 SIZE=64x32 HASH=AF63DC4C8601EC8C`.
+pub fn checkpoint(chip8: &Chip8, pc: u16) -> String {
+    let display = chip8.display();
+    format!(
+        "checkpoint PC={pc:04X} SIZE={}x{} HASH={:016X}",
+        display.width(),
+        display.height(),
+        display.hash()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use chip8::Quirks;
+
+    use super::*;
+
+    #[test]
+    fn breakpoints() {
+        let mut chip8 = Chip8::new(Quirks::default());
+        chip8.load_rom(&[0x12, 0x00]).unwrap();
+        let mut debugger = Debugger::new(0, DEFAULT_REWIND);
+        debugger.command("b 200", &mut chip8);
+        debugger.command("c", &mut chip8);
+        assert!(!debugger.paused());
+        // We've just continued from 0x200 so carry on past it.
+        assert!(!debugger.hit_breakpoint(&chip8));
+        chip8.step().unwrap();
+        assert!(debugger.hit_breakpoint(&chip8));
+        assert!(debugger.paused());
+
+        debugger.command("b 0x200", &mut chip8);
+        debugger.command("c", &mut chip8);
+        chip8.step().unwrap();
+        assert!(!debugger.hit_breakpoint(&chip8));
+        assert!(!debugger.hit_breakpoint(&chip8));
+    }
+
+    #[test]
+    fn watchpoints() {
+        let mut chip8 = Chip8::new(Quirks::default());
+        // Store V0 into the font region.
+        chip8
+            .load_rom(&[
+                0xA0, 0x10, 0x60, 0x00, 0xF0, 0x55, 0x60, 0x01, 0xA0, 0x10, 0xF0, 0x55,
+            ])
+            .unwrap();
+        let mut debugger = Debugger::new(0, DEFAULT_REWIND);
+        debugger.command("w 10", &mut chip8);
+        debugger.command("c", &mut chip8);
+        for _ in 0..3 {
+            let pc = chip8.pc();
+            chip8.step().unwrap();
+            debugger.check_watchpoints(&chip8, pc);
+            assert!(!debugger.paused());
+        }
+        for _ in 0..3 {
+            let pc = chip8.pc();
+            chip8.step().unwrap();
+            debugger.check_watchpoints(&chip8, pc);
+        }
+        assert!(debugger.paused());
+        assert_eq!(debugger.message, "020A changed 0010 from 00 to 01");
+    }
+
+    #[test]
+    fn step_back() {
+        let mut chip8 = Chip8::new(Quirks::default());
+        chip8
+            .load_rom(&[0x60, 0x01, 0x70, 0x01, 0x70, 0x01, 0x70, 0x01])
+            .unwrap();
+        let mut debugger = Debugger::new(0, 2);
+        for _ in 0..4 {
+            debugger.snapshot(&chip8);
+            chip8.step().unwrap();
+            debugger.record();
+        }
+        assert_eq!(chip8.registers()[0], 4);
+        assert!(debugger.command("r", &mut chip8));
+        assert_eq!((chip8.pc(), chip8.registers()[0]), (0x206, 3));
+        assert!(debugger.command("r", &mut chip8));
+        assert_eq!((chip8.pc(), chip8.registers()[0]), (0x204, 2));
+        // Only the last two instructions were kept.
+        assert!(!debugger.command("r", &mut chip8));
+        assert_eq!(debugger.message, "can't step back any further");
+    }
+
+    #[test]
+    fn no_rewind() {
+        let mut chip8 = Chip8::new(Quirks::default());
+        chip8.load_rom(&[0x60, 0x01]).unwrap();
+        let mut debugger = Debugger::new(0, 0);
+        debugger.snapshot(&chip8);
+        chip8.step().unwrap();
+        debugger.record();
+        assert!(debugger.snapshot.is_empty());
+        assert!(!debugger.command("r", &mut chip8));
+    }
+
+    #[test]
+    fn hex_dump() {
+        let mut mem = [0; 0x40];
+        mem[0x20..0x2B].copy_from_slice(b"CHIP-8\x00\x01\x02\x03~");
+        assert_eq!(
+            hexdump(&mem, 0x20, 0x14),
+            "0020  43 48 49 50 2D 38 00 01  02 03 7E 00 00 00 00 00  |CHIP-8....~.....|\n\
+             0030  00 00 00 00                                       |....|"
+        );
+        assert_eq!(
+            hexdump(&mem, 0x3E, 0x10),
+            "003E  00 00                                             |..|\n\
+             (truncated at the end of memory, 0040)"
+        );
+        assert_eq!(hexdump(&mem, 0x20, 0), "");
+
+        // The `x` command dumps the machine's memory, a line unless given a length.
+        let mut chip8 = Chip8::new(Quirks::default());
+        chip8.load_rom(&[0x12, 0x00]).unwrap();
+        let mut debugger = Debugger::new(0, DEFAULT_REWIND);
+        debugger.command("x 200", &mut chip8);
+        assert_eq!(debugger.message, hexdump(chip8.memory(), 0x200, 0x10));
+        debugger.command("x 0x1FE 4", &mut chip8);
+        assert!(debugger.message.starts_with("01FE  00 00 12 00 "));
+        debugger.command("x 200 zz", &mut chip8);
+        assert_eq!(debugger.message, "invalid length in `x 200 zz`");
+    }
+
+    #[test]
+    fn register_dump() {
+        let mut chip8 = Chip8::new(Quirks::default());
+        chip8.load_rom(&[0x61, 0x1F, 0xA3, 0x00]).unwrap();
+        chip8.step().unwrap();
+        chip8.step().unwrap();
+        assert_eq!(
+            registers(&chip8),
+            "V0=00 V1=1F V2=00 V3=00 V4=00 V5=00 V6=00 V7=00 V8=00 V9=00 VA=00 VB=00 VC=00 VD=00 \
+             VE=00 VF=00 I=0300 PC=0204 SP=0 DT=00 ST=00"
+        );
+        assert_eq!(
+            overlay(&chip8),
+            "PC=0204 I=0300 SP=0 DT=00 ST=00\n\
+             V0-VF 00 1F 00 00 00 00 00 00 00 00 00 00 00 00 00 00"
+        );
+        assert_eq!(overlay(&chip8).lines().count(), OVERLAY_ROWS);
+    }
+
+    #[test]
+    fn checkpoints() {
+        let mut chip8 = Chip8::new(Quirks::default());
+        let blank = checkpoint(&chip8, 0x200);
+        assert_eq!(
+            blank,
+            format!(
+                "checkpoint PC=0200 SIZE=64x32 HASH={:016X}",
+                chip8.display().hash()
+            )
+        );
+        // Drawing the 0 in the font changes the hash.
+        chip8.load_rom(&[0xF0, 0x29, 0xD0, 0x05]).unwrap();
+        chip8.step().unwrap();
+        chip8.step().unwrap();
+        assert_ne!(checkpoint(&chip8, 0x200), blank);
+    }
+
+    #[test]
+    fn dump_lines() {
+        let mut chip8 = Chip8::new(Quirks::default());
+        chip8
+            .load_rom(&[0x22, 0x04, 0x00, 0x00, 0x6A, 0x2B, 0xA3, 0x00])
+            .unwrap();
+        for _ in 0..3 {
+            chip8.step().unwrap();
+        }
+        let dump = dump(&chip8);
+        let lines: Vec<_> = dump.lines().collect();
+        assert_eq!(lines.len(), 21);
+        assert_eq!(lines[..3], ["PC=0208", "I=0300", "V0=00"]);
+        assert_eq!(lines[12], "VA=2B");
+        assert_eq!(lines[18..], ["DT=00", "ST=00", "STACK=0202"]);
+    }
+}
@@ -0,0 +1,503 @@
+//! Disassembly of CHIP-8 programs into human-readable listings.
+//!
+//! Mnemonics follow [Cowgod's technical reference](http://devernay.free.fr/hacks/chip8/C8TECH10.HTM).
+
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::fmt;
+
+/// A decoded instruction. Registers are numbered 0-15, as in `VX`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    /// `00CN`: scroll the display down N pixels (SUPER-CHIP).
+    ScrollDown(u8),
+    /// `00E0`
+    ClearScreen,
+    /// `00EE`: return from a subroutine.
+    Return,
+    /// `00FB`: scroll the display right 4 pixels (SUPER-CHIP).
+    ScrollRight,
+    /// `00FC`: scroll the display left 4 pixels (SUPER-CHIP).
+    ScrollLeft,
+    /// `00FD`: exit the interpreter (SUPER-CHIP).
+    Exit,
+    /// `00FE`: switch to low resolution (SUPER-CHIP).
+    LowRes,
+    /// `00FF`: switch to high resolution (SUPER-CHIP).
+    HighRes,
+    /// `1NNN`
+    Jump(u16),
+    /// `2NNN`: call a subroutine.
+    Call(u16),
+    /// `3XNN`: skip if VX == NN.
+    SkipIfEqual { x: u8, nn: u8 },
+    /// `4XNN`: skip if VX != NN.
+    SkipIfNotEqual { x: u8, nn: u8 },
+    /// `5XY0`: skip if VX == VY.
+    SkipIfRegsEqual { x: u8, y: u8 },
+    /// `6XNN`
+    SetReg { x: u8, nn: u8 },
+    /// `7XNN`, without carry.
+    AddToReg { x: u8, nn: u8 },
+    /// `8XY0`
+    Copy { x: u8, y: u8 },
+    /// `8XY1`
+    Or { x: u8, y: u8 },
+    /// `8XY2`
+    And { x: u8, y: u8 },
+    /// `8XY3`
+    Xor { x: u8, y: u8 },
+    /// `8XY4`: VX += VY, with carry in VF.
+    Add { x: u8, y: u8 },
+    /// `8XY5`: VX -= VY, with VF cleared on borrow.
+    Sub { x: u8, y: u8 },
+    /// `8XY6`
+    ShiftRight { x: u8, y: u8 },
+    /// `8XY7`: VX = VY - VX, with VF cleared on borrow.
+    SubFrom { x: u8, y: u8 },
+    /// `8XYE`
+    ShiftLeft { x: u8, y: u8 },
+    /// `9XY0`: skip if VX != VY.
+    SkipIfRegsNotEqual { x: u8, y: u8 },
+    /// `ANNN`
+    SetIndex(u16),
+    /// `BNNN`: jump to NNN plus V0, or VX with the jump quirk.
+    JumpOffset(u16),
+    /// `CXNN`: VX = a random byte & NN.
+    Random { x: u8, nn: u8 },
+    /// `DXYN`
+    Draw { x: u8, y: u8, n: u8 },
+    /// `EX9E`: skip if the key in VX is held.
+    SkipIfKey { x: u8 },
+    /// `EXA1`: skip if the key in VX isn't held.
+    SkipIfNotKey { x: u8 },
+    /// `F000 NNNN`: set I to the 16-bit address in the following word (XO-CHIP). The address is
+    /// `None` when only the first word was decoded.
+    SetIndexLong(Option<u16>),
+    /// `FN01`: select the planes drawn to, as a mask (XO-CHIP).
+    SelectPlanes(u8),
+    /// `F002`: load the audio pattern from I (XO-CHIP).
+    LoadAudio,
+    /// `FX07`
+    GetDelay { x: u8 },
+    /// `FX0A`
+    WaitForKey { x: u8 },
+    /// `FX15`
+    SetDelay { x: u8 },
+    /// `FX18`
+    SetSound { x: u8 },
+    /// `FX1E`
+    AddToIndex { x: u8 },
+    /// `FX29`: point I at the small font digit in VX.
+    Font { x: u8 },
+    /// `FX30`: point I at the large font digit in VX (SUPER-CHIP).
+    BigFont { x: u8 },
+    /// `FX3A`: set the audio pitch (XO-CHIP).
+    SetPitch { x: u8 },
+    /// `FX33`: store VX as three decimal digits.
+    Bcd { x: u8 },
+    /// `FX55`: store V0-VX.
+    Store { x: u8 },
+    /// `FX65`: load V0-VX.
+    Load { x: u8 },
+    /// `FX75`: save V0-VX to the RPL flags (SUPER-CHIP).
+    SaveFlags { x: u8 },
+    /// `FX85`: load V0-VX from the RPL flags (SUPER-CHIP).
+    LoadFlags { x: u8 },
+    /// An opcode the interpreter doesn't implement, such as data mixed in with the code.
+    Unknown(u16),
+}
+
+/// Decode `opcode`. Decoding mirrors [`Chip8::step`](crate::Chip8::step) so the two agree on what
+/// each opcode means.
+pub fn decode(opcode: u16) -> Instruction {
+    use Instruction::*;
+
+    let x = (opcode >> 8 & 0xf) as u8;
+    let y = (opcode >> 4 & 0xf) as u8;
+    let n = (opcode & 0xf) as u8;
+    let nn = opcode as u8;
+    let nnn = opcode & 0x0fff;
+
+    match opcode >> 12 & 0xf {
+        0x0 => match opcode {
+            0x00C0..=0x00CF => ScrollDown(n),
+            0x00E0 => ClearScreen,
+            0x00EE => Return,
+            0x00FB => ScrollRight,
+            0x00FC => ScrollLeft,
+            0x00FD => Exit,
+            0x00FE => LowRes,
+            0x00FF => HighRes,
+            _ => Unknown(opcode),
+        },
+        0x1 => Jump(nnn),
+        0x2 => Call(nnn),
+        0x3 => SkipIfEqual { x, nn },
+        0x4 => SkipIfNotEqual { x, nn },
+        0x5 => SkipIfRegsEqual { x, y },
+        0x6 => SetReg { x, nn },
+        0x7 => AddToReg { x, nn },
+        0x8 => match n {
+            0x0 => Copy { x, y },
+            0x1 => Or { x, y },
+            0x2 => And { x, y },
+            0x3 => Xor { x, y },
+            0x4 => Add { x, y },
+            0x5 => Sub { x, y },
+            0x6 => ShiftRight { x, y },
+            0x7 => SubFrom { x, y },
+            0xE => ShiftLeft { x, y },
+            _ => Unknown(opcode),
+        },
+        0x9 => SkipIfRegsNotEqual { x, y },
+        0xA => SetIndex(nnn),
+        0xB => JumpOffset(nnn),
+        0xC => Random { x, nn },
+        0xD => Draw { x, y, n },
+        0xE => match nn {
+            0x9E => SkipIfKey { x },
+            0xA1 => SkipIfNotKey { x },
+            _ => Unknown(opcode),
+        },
+        0xF => match nn {
+            0x00 if x == 0 => SetIndexLong(None),
+            0x01 => SelectPlanes(x),
+            0x02 if x == 0 => LoadAudio,
+            0x07 => GetDelay { x },
+            0x0A => WaitForKey { x },
+            0x15 => SetDelay { x },
+            0x18 => SetSound { x },
+            0x1E => AddToIndex { x },
+            0x29 => Font { x },
+            0x30 => BigFont { x },
+            0x3A => SetPitch { x },
+            0x33 => Bcd { x },
+            0x55 => Store { x },
+            0x65 => Load { x },
+            0x75 => SaveFlags { x },
+            0x85 => LoadFlags { x },
+            _ => Unknown(opcode),
+        },
+        _ => unreachable!(),
+    }
+}
+
+impl Instruction {
+    /// The pattern the instruction matches in the usual notation, such as `DXYN` or `8XY4`, or
+    /// `None` if the interpreter doesn't implement it.
+    pub fn pattern(&self) -> Option<&'static str> {
+        use Instruction::*;
+
+        Some(match self {
+            ScrollDown(_) => "00CN",
+            ClearScreen => "00E0",
+            Return => "00EE",
+            ScrollRight => "00FB",
+            ScrollLeft => "00FC",
+            Exit => "00FD",
+            LowRes => "00FE",
+            HighRes => "00FF",
+            Jump(_) => "1NNN",
+            Call(_) => "2NNN",
+            SkipIfEqual { .. } => "3XNN",
+            SkipIfNotEqual { .. } => "4XNN",
+            SkipIfRegsEqual { .. } => "5XY0",
+            SetReg { .. } => "6XNN",
+            AddToReg { .. } => "7XNN",
+            Copy { .. } => "8XY0",
+            Or { .. } => "8XY1",
+            And { .. } => "8XY2",
+            Xor { .. } => "8XY3",
+            Add { .. } => "8XY4",
+            Sub { .. } => "8XY5",
+            ShiftRight { .. } => "8XY6",
+            SubFrom { .. } => "8XY7",
+            ShiftLeft { .. } => "8XYE",
+            SkipIfRegsNotEqual { .. } => "9XY0",
+            SetIndex(_) => "ANNN",
+            JumpOffset(_) => "BNNN",
+            Random { .. } => "CXNN",
+            Draw { .. } => "DXYN",
+            SkipIfKey { .. } => "EX9E",
+            SkipIfNotKey { .. } => "EXA1",
+            SetIndexLong(_) => "F000",
+            SelectPlanes(_) => "FN01",
+            LoadAudio => "F002",
+            GetDelay { .. } => "FX07",
+            WaitForKey { .. } => "FX0A",
+            SetDelay { .. } => "FX15",
+            SetSound { .. } => "FX18",
+            AddToIndex { .. } => "FX1E",
+            Font { .. } => "FX29",
+            BigFont { .. } => "FX30",
+            SetPitch { .. } => "FX3A",
+            Bcd { .. } => "FX33",
+            Store { .. } => "FX55",
+            Load { .. } => "FX65",
+            SaveFlags { .. } => "FX75",
+            LoadFlags { .. } => "FX85",
+            Unknown(_) => return None,
+        })
+    }
+}
+
+/// The mnemonic, or `DW` and the opcode for unknown opcodes.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Instruction::*;
+
+        match *self {
+            ScrollDown(n) => write!(f, "SCD {n}"),
+            ClearScreen => write!(f, "CLS"),
+            Return => write!(f, "RET"),
+            ScrollRight => write!(f, "SCR"),
+            ScrollLeft => write!(f, "SCL"),
+            Exit => write!(f, "EXIT"),
+            LowRes => write!(f, "LOW"),
+            HighRes => write!(f, "HIGH"),
+            Jump(nnn) => write!(f, "JP #{nnn:03X}"),
+            Call(nnn) => write!(f, "CALL #{nnn:03X}"),
+            SkipIfEqual { x, nn } => write!(f, "SE V{x:X}, #{nn:02X}"),
+            SkipIfNotEqual { x, nn } => write!(f, "SNE V{x:X}, #{nn:02X}"),
+            SkipIfRegsEqual { x, y } => write!(f, "SE V{x:X}, V{y:X}"),
+            SetReg { x, nn } => write!(f, "LD V{x:X}, #{nn:02X}"),
+            AddToReg { x, nn } => write!(f, "ADD V{x:X}, #{nn:02X}"),
+            Copy { x, y } => write!(f, "LD V{x:X}, V{y:X}"),
+            Or { x, y } => write!(f, "OR V{x:X}, V{y:X}"),
+            And { x, y } => write!(f, "AND V{x:X}, V{y:X}"),
+            Xor { x, y } => write!(f, "XOR V{x:X}, V{y:X}"),
+            Add { x, y } => write!(f, "ADD V{x:X}, V{y:X}"),
+            Sub { x, y } => write!(f, "SUB V{x:X}, V{y:X}"),
+            ShiftRight { x, y } => write!(f, "SHR V{x:X}, V{y:X}"),
+            SubFrom { x, y } => write!(f, "SUBN V{x:X}, V{y:X}"),
+            ShiftLeft { x, y } => write!(f, "SHL V{x:X}, V{y:X}"),
+            SkipIfRegsNotEqual { x, y } => write!(f, "SNE V{x:X}, V{y:X}"),
+            SetIndex(nnn) => write!(f, "LD I, #{nnn:03X}"),
+            JumpOffset(nnn) => write!(f, "JP V0, #{nnn:03X}"),
+            Random { x, nn } => write!(f, "RND V{x:X}, #{nn:02X}"),
+            Draw { x, y, n } => write!(f, "DRW V{x:X}, V{y:X}, {n}"),
+            SkipIfKey { x } => write!(f, "SKP V{x:X}"),
+            SkipIfNotKey { x } => write!(f, "SKNP V{x:X}"),
+            SetIndexLong(Some(nnnn)) => write!(f, "LD I, #{nnnn:04X}"),
+            SetIndexLong(None) => write!(f, "LD I, LONG"),
+            SelectPlanes(planes) => write!(f, "PLANE {planes}"),
+            LoadAudio => write!(f, "AUDIO"),
+            GetDelay { x } => write!(f, "LD V{x:X}, DT"),
+            WaitForKey { x } => write!(f, "LD V{x:X}, K"),
+            SetDelay { x } => write!(f, "LD DT, V{x:X}"),
+            SetSound { x } => write!(f, "LD ST, V{x:X}"),
+            AddToIndex { x } => write!(f, "ADD I, V{x:X}"),
+            Font { x } => write!(f, "LD F, V{x:X}"),
+            BigFont { x } => write!(f, "LD HF, V{x:X}"),
+            SetPitch { x } => write!(f, "PITCH V{x:X}"),
+            Bcd { x } => write!(f, "LD B, V{x:X}"),
+            Store { x } => write!(f, "LD [I], V{x:X}"),
+            Load { x } => write!(f, "LD V{x:X}, [I]"),
+            SaveFlags { x } => write!(f, "LD R, V{x:X}"),
+            LoadFlags { x } => write!(f, "LD V{x:X}, R"),
+            Unknown(opcode) => write!(f, "DW #{opcode:04X}"),
+        }
+    }
+}
+
+/// The mnemonic for `opcode`, or `None` if the interpreter doesn't implement it.
+pub fn mnemonic(opcode: u16) -> Option<String> {
+    let instruction = decode(opcode);
+    instruction.pattern().map(|_| instruction.to_string())
+}
+
+/// The pattern `opcode` matches in the usual notation, such as `DXYN` or `8XY4`, or `None` if the
+/// interpreter doesn't implement it.
+pub fn pattern(opcode: u16) -> Option<&'static str> {
+    decode(opcode).pattern()
+}
+
+/// Decode `rom`, as loaded at `origin`, word by word, giving the address of each instruction.
+/// `F000 NNNN` is decoded as one instruction, and a trailing odd byte is left out.
+pub fn instructions(rom: &[u8], origin: usize) -> impl Iterator<Item = (u16, Instruction)> + '_ {
+    let mut offset = 0;
+    core::iter::from_fn(move || {
+        let addr = (origin + offset) as u16;
+        let (instruction, len) = match *rom.get(offset..)? {
+            [0xF0, 0x00, hi, lo, ..] => (
+                Instruction::SetIndexLong(Some(u16::from_be_bytes([hi, lo]))),
+                4,
+            ),
+            [hi, lo, ..] => (decode(u16::from_be_bytes([hi, lo])), 2),
+            _ => return None,
+        };
+        offset += len;
+        Some((addr, instruction))
+    })
+}
+
+/// Disassemble `rom` as loaded at `origin`, returning the address, raw opcode and mnemonic of each
+/// two-byte word, or four bytes for `F000 NNNN`. Words which aren't valid instructions are
+/// rendered as `DW` data, and a trailing odd byte as `DB`.
+pub fn disassemble(rom: &[u8], origin: usize) -> Vec<(u16, u16, String)> {
+    let mut listing = Vec::new();
+    let mut offset = 0;
+    while offset < rom.len() {
+        let addr = (origin + offset) as u16;
+        match rom[offset..] {
+            [0xF0, 0x00, hi, lo, ..] => {
+                let nnnn = u16::from_be_bytes([hi, lo]);
+                let instruction = Instruction::SetIndexLong(Some(nnnn));
+                listing.push((addr, 0xF000, instruction.to_string()));
+                offset += 4;
+            }
+            [hi, lo, ..] => {
+                let opcode = u16::from_be_bytes([hi, lo]);
+                listing.push((addr, opcode, decode(opcode).to_string()));
+                offset += 2;
+            }
+            [b] => {
+                listing.push((addr, b as u16, format!("DB #{b:02X}")));
+                offset += 1;
+            }
+            [] => unreachable!("offset is within the ROM"),
+        }
+    }
+    listing
+}
+
+/// The addresses and opcodes of instructions in `rom`, loaded at `origin`, which the interpreter
+/// doesn't implement.
+///
+/// Code and data are mixed together in a ROM, so rather than checking every word this follows
+/// jumps, calls and skips from the start of the program. Jumps with `BNNN` can't be followed, so
+/// code only reached that way isn't checked.
+pub fn unsupported(rom: &[u8], origin: usize) -> Vec<(u16, u16)> {
+    let word = |addr: u16| {
+        // Addresses below the program can't be in the ROM.
+        let offset = (addr as usize).checked_sub(origin)?;
+        match rom.get(offset..offset + 2)? {
+            &[hi, lo] => Some(u16::from_be_bytes([hi, lo])),
+            _ => unreachable!("two bytes"),
+        }
+    };
+    // `F000 NNNN` is twice as long as other instructions.
+    let after = |addr: u16| match word(addr) {
+        Some(0xF000) => addr.wrapping_add(4),
+        _ => addr.wrapping_add(2),
+    };
+    let mut seen = vec![false; 0x10000];
+    let mut todo = vec![origin as u16];
+    let mut found = Vec::new();
+    while let Some(addr) = todo.pop() {
+        let Some(opcode) = word(addr) else {
+            continue;
+        };
+        if core::mem::replace(&mut seen[addr as usize], true) {
+            continue;
+        }
+        let next = after(addr);
+        match pattern(opcode) {
+            None => found.push((addr, opcode)),
+            Some("00EE" | "00FD" | "BNNN") => {}
+            Some("1NNN") => todo.push(opcode & 0x0fff),
+            Some("2NNN") => todo.extend([opcode & 0x0fff, next]),
+            Some("3XNN" | "4XNN" | "5XY0" | "9XY0" | "EX9E" | "EXA1") => {
+                todo.extend([next, after(next)])
+            }
+            Some(_) => todo.push(next),
+        }
+    }
+    found.sort_unstable();
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patterns() {
+        // Every implemented opcode has a pattern, and only those.
+        for opcode in 0..=u16::MAX {
+            assert_eq!(
+                pattern(opcode).is_some(),
+                mnemonic(opcode).is_some(),
+                "{opcode:04X}"
+            );
+        }
+        assert_eq!(pattern(0xD01F), Some("DXYN"));
+        assert_eq!(pattern(0x00C3), Some("00CN"));
+    }
+
+    #[test]
+    fn listing() {
+        assert_eq!(
+            disassemble(
+                &[0x00, 0xE0, 0xA2, 0x2A, 0xD0, 0x1F, 0xF3, 0x33, 0xFF, 0xFF, 0x12],
+                0x200
+            ),
+            [
+                (0x200, 0x00E0, "CLS".to_owned()),
+                (0x202, 0xA22A, "LD I, #22A".to_owned()),
+                (0x204, 0xD01F, "DRW V0, V1, 15".to_owned()),
+                (0x206, 0xF333, "LD B, V3".to_owned()),
+                (0x208, 0xFFFF, "DW #FFFF".to_owned()),
+                (0x20A, 0x12, "DB #12".to_owned()),
+            ]
+        );
+        assert_eq!(
+            disassemble(&[0xF0, 0x00, 0x12, 0x34, 0xF0, 0x00], 0x200),
+            [
+                (0x200, 0xF000, "LD I, #1234".to_owned()),
+                (0x204, 0xF000, "LD I, LONG".to_owned()),
+            ]
+        );
+        assert_eq!(
+            disassemble(&[0x00, 0xE0], 0x600),
+            [(0x600, 0x00E0, "CLS".to_owned())]
+        );
+    }
+
+    #[test]
+    fn decoded_instructions() {
+        let rom = [
+            0x00, 0xE0, 0xD0, 0x1F, 0xF0, 0x00, 0x12, 0x34, 0xFF, 0xFF, 0x12,
+        ];
+        assert_eq!(
+            instructions(&rom, 0x200).collect::<Vec<_>>(),
+            [
+                (0x200, Instruction::ClearScreen),
+                (0x202, Instruction::Draw { x: 0, y: 1, n: 15 }),
+                (0x204, Instruction::SetIndexLong(Some(0x1234))),
+                (0x208, Instruction::Unknown(0xFFFF)),
+            ]
+        );
+        assert_eq!(decode(0x6A2B), Instruction::SetReg { x: 0xA, nn: 0x2B });
+        assert_eq!(decode(0xF000), Instruction::SetIndexLong(None));
+    }
+
+    #[test]
+    fn unsupported_opcodes() {
+        let rom = [
+            0x22, 0x0A, // 200: CALL #20A
+            0x30, 0x00, // 202: SE V0, #00
+            0x60, 0x01, // 204: LD V0, #01
+            0x12, 0x0E, // 206: JP #20E
+            0xFF, 0xFF, // 208: data, never reached
+            0xE0, 0xFF, // 20A: unsupported
+            0x00, 0xEE, // 20C: RET
+            0x80, 0x08, // 20E: unsupported
+            0x10, 0x00, // 210: JP #000, outside the ROM
+        ];
+        assert_eq!(unsupported(&rom, 0x200), [(0x20A, 0xE0FF), (0x20E, 0x8008)]);
+
+        // Skips step over both halves of `F000 NNNN`.
+        let rom = [
+            0x30, 0x00, // 200: SE V0, #00
+            0xF0, 0x00, 0xFF, 0xFF, // 202: LD I, #FFFF
+            0x00, 0xFD, // 206: EXIT
+        ];
+        assert!(unsupported(&rom, 0x200).is_empty());
+    }
+}
@@ -0,0 +1,174 @@
+//! The display's geometry and pixels, which change size with the resolution.
+
+use alloc::{vec, vec::Vec};
+
+use crate::{quirks::fnv1a, HEIGHT, WIDTH};
+
+/// A display of `width` by `height` pixels, one byte each. Each is 0 or 1, or for XO-CHIP programs
+/// up to 3 with bit 0 set for the first plane and bit 1 for the second.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Display {
+    width: usize,
+    height: usize,
+    /// Row by row.
+    pixels: Vec<u8>,
+}
+
+impl Display {
+    /// A blank display of `width` by `height` pixels.
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![0; width * height],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Width and height.
+    pub fn dimensions(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    /// The pixels, row by row.
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    pub(crate) fn pixels_mut(&mut self) -> &mut [u8] {
+        &mut self.pixels
+    }
+
+    /// The pixel at column `x` of row `y`.
+    pub fn get(&self, x: usize, y: usize) -> u8 {
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, value: u8) {
+        self.pixels[y * self.width + x] = value;
+    }
+
+    /// Flip the bits in `planes` of the pixel at column `x` of row `y`, returning whether any of
+    /// them were lit, which is a collision for `DXYN`.
+    pub fn xor(&mut self, x: usize, y: usize, planes: u8) -> bool {
+        let pixel = &mut self.pixels[y * self.width + x];
+        let collision = *pixel & planes != 0;
+        *pixel ^= planes;
+        collision
+    }
+
+    /// Turn off the bits in `planes` of every pixel, leaving any other planes alone.
+    pub fn clear(&mut self, planes: u8) {
+        for pixel in &mut self.pixels {
+            *pixel &= !planes;
+        }
+    }
+
+    /// The 64-bit FNV-1a hash of the pixels, to compare what's shown without keeping a copy.
+    pub fn hash(&self) -> u64 {
+        fnv1a(&self.pixels)
+    }
+
+    /// Whether every pixel is off.
+    pub fn is_blank(&self) -> bool {
+        self.pixels.iter().all(|&p| p == 0)
+    }
+
+    /// Switch to `width` by `height`, blanking the display.
+    pub fn resize(&mut self, width: usize, height: usize) {
+        self.width = width;
+        self.height = height;
+        self.pixels.clear();
+        self.pixels.resize(width * height, 0);
+    }
+
+    // Scrolling is by pixels of the current resolution, as in Octo and most modern interpreters.
+    // The original SUPER-CHIP on the HP 48 scrolled half as far in low resolution. Only the
+    // bits in `planes` move.
+    pub(crate) fn scroll_down(&mut self, n: usize, planes: u8) {
+        let n = n.min(self.height) * self.width;
+        for i in (0..self.pixels.len()).rev() {
+            let from = if i >= n { self.pixels[i - n] } else { 0 };
+            self.pixels[i] = self.pixels[i] & !planes | from & planes;
+        }
+    }
+
+    pub(crate) fn scroll_right(&mut self, n: usize, planes: u8) {
+        for row in self.pixels.chunks_mut(self.width) {
+            for x in (0..row.len()).rev() {
+                let from = if x >= n { row[x - n] } else { 0 };
+                row[x] = row[x] & !planes | from & planes;
+            }
+        }
+    }
+
+    pub(crate) fn scroll_left(&mut self, n: usize, planes: u8) {
+        for row in self.pixels.chunks_mut(self.width) {
+            for x in 0..row.len() {
+                let from = row.get(x + n).copied().unwrap_or(0);
+                row[x] = row[x] & !planes | from & planes;
+            }
+        }
+    }
+}
+
+impl Default for Display {
+    /// The standard low resolution display.
+    fn default() -> Self {
+        Self::new(WIDTH, HEIGHT)
+    }
+}
+
+impl Clone for Display {
+    fn clone(&self) -> Self {
+        Self {
+            width: self.width,
+            height: self.height,
+            pixels: self.pixels.clone(),
+        }
+    }
+
+    /// Reuses the pixel buffer, so that copying frames doesn't allocate once it's big enough.
+    fn clone_from(&mut self, source: &Self) {
+        self.width = source.width;
+        self.height = source.height;
+        self.pixels.clone_from(&source.pixels);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pixels() {
+        let mut display = Display::new(4, 2);
+        display.set(3, 1, 1);
+        assert_eq!(display.pixels(), [0, 0, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(display.get(3, 1), 1);
+
+        // Drawing in both planes, colliding only where a plane was already lit.
+        assert!(!display.xor(0, 0, 0b10));
+        assert!(display.xor(0, 0, 0b11));
+        assert_eq!(display.get(0, 0), 0b01);
+        assert!(!display.is_blank());
+
+        display.clear(0b10);
+        assert_eq!(display.get(0, 0), 0b01);
+        display.clear(0b11);
+        assert!(display.is_blank());
+
+        display.set(0, 0, 1);
+        assert_eq!(display.hash(), fnv1a(&[1, 0, 0, 0, 0, 0, 0, 0]));
+        display.resize(2, 3);
+        assert_eq!(display.dimensions(), (2, 3));
+        assert_eq!(display.pixels(), [0; 6]);
+    }
+}
@@ -0,0 +1,536 @@
+//! Keyboard input from the terminal.
+//!
+//! The terminal is put into a raw-ish mode with `stty` and asked to report key presses and
+//! releases using the [kitty keyboard protocol](https://sw.kovidgoyal.net/kitty/keyboard-protocol/).
+//! Terminals which don't support the protocol only report presses, so these are treated as taps
+//! which are released again if they aren't repeated within a short hold time.
+//!
+//! Keys are read from the controlling terminal, `/dev/tty`, rather than stdin so that stdin can be
+//! used to pipe in the ROM.
+
+use std::{
+    ffi::{c_int, c_ulong},
+    fs::File,
+    io::{self, Read, Write},
+    os::fd::AsRawFd,
+    process::Command,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc,
+    },
+    time::{Duration, Instant},
+};
+
+/// How long a tapped key is held down for when the terminal can't report releases, unless
+/// configured otherwise. Keys are only released between reads, so at least every 100ms.
+pub const DEFAULT_KEY_HOLD: Duration = Duration::from_millis(120);
+
+/// An input event for the emulator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Input {
+    /// Keypad key pressed.
+    Down(u8),
+    /// Keypad key released.
+    Up(u8),
+    /// Speed up the instruction clock.
+    Faster,
+    /// Slow down the instruction clock.
+    Slower,
+    /// A character was typed, for the debugger. Enter is `'\n'` and backspace `'\x7F'`.
+    Char(char),
+    /// Pause or resume the emulator.
+    Pause,
+    /// Whether the turbo key is held down to speed up the instruction clock.
+    Turbo(bool),
+    /// Restart the ROM from the beginning.
+    Reset,
+    /// Save a snapshot of the machine.
+    SaveState,
+    /// Restore the last saved snapshot.
+    LoadState,
+    /// Save the display as an image.
+    Screenshot,
+    /// Show or hide the registers below the display.
+    Overlay,
+    /// The user asked to quit.
+    Quit,
+}
+
+/// A key reported by the terminal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Key {
+    Char(char),
+    /// Function key F1-F12.
+    F(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum KeyKind {
+    Press,
+    Repeat,
+    Release,
+    /// A press from a terminal that won't tell us about the release.
+    Tap,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct KeyEvent {
+    key: Key,
+    kind: KeyKind,
+    ctrl: bool,
+}
+
+/// Which keyboard key is mapped onto each key of the COSMAC VIP hex keypad.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyMap([char; 16]);
+
+impl KeyMap {
+    /// The keypad key mapped to the keyboard key `c`.
+    pub fn keypad(&self, c: char) -> Option<u8> {
+        let c = c.to_ascii_lowercase();
+        self.0.iter().position(|&k| k == c).map(|k| k as u8)
+    }
+}
+
+impl Default for KeyMap {
+    /// The standard 1234/QWER/ASDF/ZXCV layout.
+    fn default() -> Self {
+        Self([
+            'x', '1', '2', '3', 'q', 'w', 'e', 'a', 's', 'd', 'z', 'c', '4', 'r', 'f', 'v',
+        ])
+    }
+}
+
+impl FromStr for KeyMap {
+    type Err = String;
+
+    /// Parse a comma-separated list of assignments like `0=x,1=1,2=2`, which must cover every
+    /// keypad key exactly once.
+    fn from_str(s: &str) -> Result<Self, String> {
+        let mut keys = [None; 16];
+        for assignment in s.split(',') {
+            let (k, c) = assignment
+                .split_once('=')
+                .ok_or_else(|| format!("expected `<keypad key>=<key>` but got `{assignment}`"))?;
+            let k = match k.chars().collect::<Vec<_>>()[..] {
+                [k] => k.to_digit(16),
+                _ => None,
+            }
+            .ok_or_else(|| format!("`{k}` isn't a keypad key, expected 0-F"))?
+                as usize;
+            let c = match c.chars().collect::<Vec<_>>()[..] {
+                // Only keys which type the same character on every keyboard, which the window
+                // can also read.
+                [c] if c.is_ascii_graphic() || c == ' ' => c.to_ascii_lowercase(),
+                [_] => return Err(format!("`{c}` isn't a printable ASCII key")),
+                _ => return Err(format!("`{c}` isn't a single key")),
+            };
+            if keys[k].is_some() {
+                return Err(format!("keypad key {k:X} is assigned more than once"));
+            }
+            if let Some(other) = keys.iter().position(|&o| o == Some(c)) {
+                return Err(format!(
+                    "`{c}` is assigned to both keypad keys {other:X} and {k:X}"
+                ));
+            }
+            keys[k] = Some(c);
+        }
+        let mut map = [' '; 16];
+        for (k, (m, c)) in map.iter_mut().zip(keys).enumerate() {
+            *m = c.ok_or_else(|| format!("keypad key {k:X} isn't assigned"))?;
+        }
+        Ok(Self(map))
+    }
+}
+
+/// Parse the modifier and event type parameter `mods[:event]` of a CSI sequence.
+fn modifiers(param: Option<&str>) -> (bool, KeyKind) {
+    let mut parts = param.unwrap_or("").split(':');
+    let mods = parts.next().and_then(|m| m.parse::<u8>().ok()).unwrap_or(1);
+    let kind = match parts.next() {
+        Some("2") => KeyKind::Repeat,
+        Some("3") => KeyKind::Release,
+        _ => KeyKind::Press,
+    };
+    (mods.saturating_sub(1) & 0b100 != 0, kind)
+}
+
+/// Parse a single key from the start of `buf`, returning the number of bytes consumed and the
+/// key, if any. Returns `None` if `buf` holds an incomplete escape sequence or UTF-8 character,
+/// including a lone escape, which may be the start of a sequence split across reads.
+fn parse(buf: &[u8]) -> Option<(usize, Option<KeyEvent>)> {
+    let tap = |key| KeyEvent {
+        key,
+        kind: KeyKind::Tap,
+        ctrl: false,
+    };
+    match *buf {
+        [] => None,
+        [0x1B, b'[', ref rest @ ..] => {
+            let end = rest.iter().position(|b| (0x40..=0x7E).contains(b))?;
+            let params = std::str::from_utf8(&rest[..end]).unwrap_or("");
+            let mut params = params.split(';');
+            let first = params.next().unwrap_or("");
+            let (ctrl, kind) = modifiers(params.next());
+            let number = first.split(':').next().and_then(|n| n.parse::<u32>().ok());
+            let key = match (rest[end], number) {
+                (b'u', Some(c)) => char::from_u32(c).map(Key::Char),
+                (b'P'..=b'S', _) => Some(Key::F(rest[end] - b'P' + 1)),
+                (b'~', Some(n @ 11..=15)) => Some(Key::F(n as u8 - 10)),
+                (b'~', Some(n @ 17..=21)) => Some(Key::F(n as u8 - 11)),
+                (b'~', Some(n @ 23..=24)) => Some(Key::F(n as u8 - 12)),
+                _ => None,
+            };
+            Some((end + 3, key.map(|key| KeyEvent { key, kind, ctrl })))
+        }
+        [0x1B, b'O', f @ b'P'..=b'S', ..] => Some((3, Some(tap(Key::F(f - b'P' + 1))))),
+        [0x1B, b'O'] => None,
+        [0x1B] => None,
+        // An alt-modified key, which we don't care about.
+        [0x1B, ..] => Some((2, None)),
+        [0x03, ..] => Some((
+            1,
+            Some(KeyEvent {
+                key: Key::Char('c'),
+                kind: KeyKind::Tap,
+                ctrl: true,
+            }),
+        )),
+        [b @ ..0x80, ..] => Some((1, Some(tap(Key::Char(b as char))))),
+        [b, ..] => {
+            // The length of the character is given by its first byte. Stray continuation bytes and
+            // invalid characters are skipped a byte at a time.
+            let len = match b {
+                0xC0..=0xDF => 2,
+                0xE0..=0xEF => 3,
+                0xF0..=0xF7 => 4,
+                _ => return Some((1, None)),
+            };
+            let bytes = buf.get(..len)?;
+            match std::str::from_utf8(bytes)
+                .ok()
+                .and_then(|s| s.chars().next())
+            {
+                Some(c) => Some((len, Some(tap(Key::Char(c))))),
+                None => Some((1, None)),
+            }
+        }
+    }
+}
+
+/// Read keys from the controlling terminal, `/dev/tty`, sending keypad events over `tx`, so that
+/// stdin is free for the ROM. Tapped keys are released once they haven't been seen for `hold`.
+/// Returns straight away if there's no terminal, and otherwise once the receiver has hung up or
+/// reading the terminal fails. Reads time out every 100ms, so an idle terminal doesn't end it.
+pub fn run<T: From<Input>>(tx: mpsc::Sender<T>, keys: KeyMap, hold: Duration) {
+    let Ok(mut tty) = tty() else {
+        return;
+    };
+    let mut buf = Vec::new();
+    let mut chunk = [0; 64];
+    // When each currently tapped key was last seen, so that it can be released after `hold`.
+    let mut tapped: [Option<Instant>; 16] = [None; 16];
+    let mut turbo_tapped = None;
+    loop {
+        // The terminal is in non-canonical mode with a read timeout, so this returns at least
+        // every 100ms even with no input.
+        let Ok(n) = tty.read(&mut chunk) else {
+            return;
+        };
+        buf.extend_from_slice(&chunk[..n]);
+
+        let mut consumed = 0;
+        while let Some((len, event)) = parse(&buf[consumed..]) {
+            consumed += len;
+            let Some(KeyEvent { key, kind, ctrl }) = event else {
+                continue;
+            };
+            let input = match key {
+                Key::Char('c') if ctrl => Some(Input::Quit),
+                Key::Char('\t') => match kind {
+                    KeyKind::Press | KeyKind::Repeat => Some(Input::Turbo(true)),
+                    KeyKind::Release => Some(Input::Turbo(false)),
+                    KeyKind::Tap => turbo_tapped
+                        .replace(Instant::now())
+                        .is_none()
+                        .then_some(Input::Turbo(true)),
+                },
+                // Keypad keys take priority so that any key can be mapped onto the keypad.
+                Key::Char(c) => match keys.keypad(c) {
+                    Some(k) => match kind {
+                        KeyKind::Press | KeyKind::Repeat => Some(Input::Down(k)),
+                        KeyKind::Release => Some(Input::Up(k)),
+                        KeyKind::Tap => tapped[k as usize]
+                            .replace(Instant::now())
+                            .is_none()
+                            .then_some(Input::Down(k)),
+                    },
+                    None if kind == KeyKind::Release => None,
+                    None => match c {
+                        '=' | '+' => Some(Input::Faster),
+                        '-' | '_' => Some(Input::Slower),
+                        ' ' if kind != KeyKind::Repeat => Some(Input::Pause),
+                        _ => None,
+                    },
+                },
+                Key::F(1) if matches!(kind, KeyKind::Press | KeyKind::Tap) => Some(Input::Reset),
+                Key::F(2) if matches!(kind, KeyKind::Press | KeyKind::Tap) => Some(Input::Overlay),
+                Key::F(5) if matches!(kind, KeyKind::Press | KeyKind::Tap) => {
+                    Some(Input::SaveState)
+                }
+                Key::F(9) if matches!(kind, KeyKind::Press | KeyKind::Tap) => {
+                    Some(Input::LoadState)
+                }
+                Key::F(12) if matches!(kind, KeyKind::Press | KeyKind::Tap) => {
+                    Some(Input::Screenshot)
+                }
+                Key::F(_) => None,
+            };
+            if let Some(input) = input {
+                if tx.send(input.into()).is_err() {
+                    return;
+                }
+            }
+            // Typed characters are also passed on as text for the debugger.
+            match key {
+                Key::Char(c) if !ctrl && kind != KeyKind::Release => {
+                    let c = if c == '\r' { '\n' } else { c };
+                    if tx.send(Input::Char(c).into()).is_err() {
+                        return;
+                    }
+                }
+                _ => {}
+            }
+        }
+        buf.drain(..consumed);
+        // What's left is an incomplete sequence or character. If nothing more arrived for a whole
+        // read timeout, it's a lone escape or a garbled key, so drop it rather than waiting for
+        // the rest forever.
+        if n == 0 {
+            buf.clear();
+        }
+
+        for (k, last_seen) in tapped.iter_mut().enumerate() {
+            if last_seen.is_some_and(|t| t.elapsed() >= hold) {
+                *last_seen = None;
+                if tx.send(Input::Up(k as u8).into()).is_err() {
+                    return;
+                }
+            }
+        }
+        if turbo_tapped.is_some_and(|t| t.elapsed() >= hold) {
+            turbo_tapped = None;
+            if tx.send(Input::Turbo(false).into()).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Open the controlling terminal.
+fn tty() -> io::Result<File> {
+    File::open("/dev/tty")
+}
+
+/// Run `stty` on the controlling terminal, returning its stdout.
+fn stty(args: &[&str]) -> io::Result<String> {
+    let output = Command::new("stty").args(args).stdin(tty()?).output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(
+            String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+}
+
+// The little of libc we need to follow the terminal's size, which std doesn't wrap.
+extern "C" {
+    fn ioctl(fd: c_int, request: c_ulong, ...) -> c_int;
+    fn signal(signal: c_int, handler: extern "C" fn(c_int)) -> usize;
+}
+
+const SIGWINCH: c_int = 28;
+#[cfg(target_os = "linux")]
+const TIOCGWINSZ: c_ulong = 0x5413;
+// macOS and the BSDs.
+#[cfg(not(target_os = "linux"))]
+const TIOCGWINSZ: c_ulong = 0x4008_7468;
+
+/// `struct winsize`, filled in by `TIOCGWINSZ`.
+#[repr(C)]
+#[derive(Default)]
+struct WinSize {
+    rows: u16,
+    columns: u16,
+    x_pixels: u16,
+    y_pixels: u16,
+}
+
+/// Set by the `SIGWINCH` handler when the terminal is resized.
+static RESIZED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_resize(_signal: c_int) {
+    RESIZED.store(true, Ordering::Relaxed);
+}
+
+/// Start noticing when the terminal is resized, for [`resized`].
+pub fn watch_resizes() {
+    // SAFETY: the handler only stores to an atomic, which is safe to do in a signal handler.
+    unsafe {
+        signal(SIGWINCH, on_resize);
+    }
+}
+
+/// Whether the terminal has been resized since the last call, once [`watch_resizes`] is called.
+pub fn resized() -> bool {
+    RESIZED.swap(false, Ordering::Relaxed)
+}
+
+/// Size of the controlling terminal in columns and rows.
+pub fn terminal_size() -> io::Result<(usize, usize)> {
+    let tty = tty()?;
+    let mut size = WinSize::default();
+    // SAFETY: `TIOCGWINSZ` writes a `struct winsize` to the pointer, which outlives the call.
+    if unsafe { ioctl(tty.as_raw_fd(), TIOCGWINSZ, &mut size as *mut WinSize) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok((size.columns as usize, size.rows as usize))
+}
+
+/// Guard which keeps the terminal in raw mode, restoring the previous mode when dropped.
+pub struct RawMode {
+    saved: String,
+}
+
+impl RawMode {
+    pub fn enable() -> io::Result<Self> {
+        let saved = stty(&["-g"])?;
+        // Echo and line buffering off, Ctrl-C delivered as a key rather than SIGINT, and reads
+        // time out after 100ms so that `run` can release tapped keys.
+        stty(&["-icanon", "-echo", "-isig", "min", "0", "time", "1"])?;
+        // Push the kitty keyboard protocol flags to disambiguate keys, report releases and report
+        // all keys as escape codes.
+        print!("\x1B[>11u");
+        drop(io::stdout().flush());
+        Ok(Self { saved })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        print!("\x1B[<u");
+        drop(io::stdout().flush());
+        drop(stty(&[&self.saved]));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_map() {
+        let default = KeyMap::default();
+        assert_eq!(default.keypad('X'), Some(0x0));
+        assert_eq!(default.keypad('v'), Some(0xF));
+        assert_eq!(default.keypad('p'), None);
+
+        let azerty = "0=x,1=1,2=2,3=3,4=a,5=z,6=e,7=q,8=s,9=d,A=w,B=c,C=4,D=r,E=f,F=v";
+        let map: KeyMap = azerty.parse().unwrap();
+        assert_eq!(map.keypad('Q'), Some(0x7));
+        assert_eq!(map.keypad('z'), Some(0x5));
+
+        assert_eq!(
+            "0=x".parse::<KeyMap>(),
+            Err("keypad key 1 isn't assigned".to_owned())
+        );
+        assert_eq!(
+            azerty.replace("F=v", "F=q").parse::<KeyMap>(),
+            Err("`q` is assigned to both keypad keys 7 and F".to_owned())
+        );
+        assert_eq!(
+            azerty.replace("F=v", "0=v").parse::<KeyMap>(),
+            Err("keypad key 0 is assigned more than once".to_owned())
+        );
+        assert!(azerty.replace("F=v", "G=v").parse::<KeyMap>().is_err());
+        assert!(azerty.replace("F=v", "F=vv").parse::<KeyMap>().is_err());
+        assert_eq!(
+            azerty.replace("F=v", "F=é").parse::<KeyMap>(),
+            Err("`é` isn't a printable ASCII key".to_owned())
+        );
+        assert!(azerty.replace("F=v", "F=\t").parse::<KeyMap>().is_err());
+        assert_eq!(
+            azerty
+                .replace("F=v", "F= ")
+                .parse::<KeyMap>()
+                .unwrap()
+                .keypad(' '),
+            Some(0xF)
+        );
+    }
+
+    #[test]
+    fn parse_keys() {
+        let event = |key, kind, ctrl| Some(KeyEvent { key, kind, ctrl });
+        assert_eq!(
+            parse(b"q"),
+            Some((1, event(Key::Char('q'), KeyKind::Tap, false)))
+        );
+        assert_eq!(
+            parse(b"\x03"),
+            Some((1, event(Key::Char('c'), KeyKind::Tap, true)))
+        );
+        assert_eq!(
+            parse(b"\x1B[113u"),
+            Some((6, event(Key::Char('q'), KeyKind::Press, false)))
+        );
+        assert_eq!(
+            parse(b"\x1B[113;1:3u"),
+            Some((10, event(Key::Char('q'), KeyKind::Release, false)))
+        );
+        assert_eq!(
+            parse(b"\x1B[99;5u"),
+            Some((7, event(Key::Char('c'), KeyKind::Press, true)))
+        );
+        assert_eq!(
+            parse(b"\x1B[9;1:3u"),
+            Some((8, event(Key::Char('\t'), KeyKind::Release, false)))
+        );
+        assert_eq!(
+            parse(b"\x1BOP"),
+            Some((3, event(Key::F(1), KeyKind::Tap, false)))
+        );
+        assert_eq!(
+            parse(b"\x1B[15~"),
+            Some((5, event(Key::F(5), KeyKind::Press, false)))
+        );
+        assert_eq!(
+            parse(b"\x1B[20;1:3~"),
+            Some((9, event(Key::F(9), KeyKind::Release, false)))
+        );
+        assert_eq!(parse(b"\x1B[11"), None);
+        assert_eq!(parse(b"\x1B"), None);
+        assert_eq!(parse(b"\x1Bq"), Some((2, None)));
+    }
+
+    #[test]
+    fn parse_utf8() {
+        let tap = |c| {
+            Some(KeyEvent {
+                key: Key::Char(c),
+                kind: KeyKind::Tap,
+                ctrl: false,
+            })
+        };
+        assert_eq!(parse("éq".as_bytes()), Some((2, tap('é'))));
+        assert_eq!(parse("€".as_bytes()), Some((3, tap('€'))));
+        assert_eq!(parse("🦀".as_bytes()), Some((4, tap('🦀'))));
+        // A character split across reads waits for the rest of it.
+        assert_eq!(parse(&"€".as_bytes()[..2]), None);
+        // Stray continuation bytes and invalid characters are skipped.
+        assert_eq!(parse(b"\x80q"), Some((1, None)));
+        assert_eq!(parse(b"\xC3q"), Some((1, None)));
+        assert_eq!(parse(b"\xFF"), Some((1, None)));
+    }
+}
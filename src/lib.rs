@@ -0,0 +1,2046 @@
+//! A CHIP-8 interpreter, independent of any particular frontend.
+//!
+//! The interpreter doesn't start any threads of its own, so it can be driven by a host on its own
+//! schedule, e.g. from `requestAnimationFrame` when compiled to WebAssembly: pass keypad input to
+//! [`Chip8::set_key`] and call [`Chip8::run_frame`] 60 times a second, or [`Chip8::step`] and
+//! [`Chip8::tick_timers`] for finer control.
+//!
+//! Nor does it assume anything about how the display is shown: [`Chip8::framebuffer`] has one byte
+//! per pixel of the emulated display, [`Chip8::dimensions`] in size, and it's up to the frontend to
+//! scale that to its output, whether that's half-block characters in a terminal or squares of
+//! pixels in a window.
+//!
+//! Without the default `std` feature the crate is `no_std`, needing only `alloc`, so that it runs
+//! on microcontrollers too. [`Chip8::step`], [`Chip8::set_key`], [`Chip8::tick_timers`] and
+//! [`Chip8::framebuffer`] are all such a port needs, with the host supplying the clock and the
+//! screen. The terminal frontend is the `chip8` binary, which needs `std`.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+use alloc::{boxed::Box, string::String, sync::Arc, vec, vec::Vec};
+use core::{
+    fmt,
+    sync::atomic::{AtomicBool, AtomicU8, Ordering},
+};
+
+use lock::Lock;
+
+pub mod asm;
+pub mod disasm;
+mod display;
+mod lock;
+mod quirks;
+mod state;
+
+pub use display::Display;
+pub use quirks::Quirks;
+
+/// Size of the standard low-resolution display.
+pub const WIDTH: usize = 64;
+pub const HEIGHT: usize = 32;
+/// Size of the SUPER-CHIP high-resolution display.
+pub const HIRES_WIDTH: usize = 128;
+pub const HIRES_HEIGHT: usize = 64;
+/// Address of the built-in hexadecimal font in memory.
+pub const FONT_BASE: usize = 0x4F;
+/// Instructions executed per second unless configured otherwise.
+pub const DEFAULT_HZ: u32 = 700;
+/// Instructions executed per 60Hz frame with frame timing unless configured otherwise, about the
+/// same speed as [`DEFAULT_HZ`].
+pub const DEFAULT_CYCLES_PER_FRAME: u32 = 11;
+/// Largest ROM that fits in the standard 4KB of memory above 0x200.
+pub const MAX_ROM_SIZE: usize = 4096 - 0x200;
+/// How many of the most recently executed instructions are checked for being overwritten.
+const RECENT_FETCHES: usize = 16;
+
+/// Address of the SUPER-CHIP large hexadecimal font in memory, just after the small font.
+pub const BIG_FONT_BASE: usize = FONT_BASE + FONT_DATA.len();
+/// First address after the fonts, the lowest a program can be loaded at.
+pub const FONT_END: usize = BIG_FONT_BASE + BIG_FONT_DATA.len();
+
+/// Sprites for the hexadecimal digits, 5 bytes each.
+const FONT_DATA: [u8; 80] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+    0x20, 0x60, 0x20, 0x20, 0x70, // 1
+    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+    0x90, 0x90, 0xF0, 0x10, 0x10, // 4
+    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+    0xF0, 0x10, 0x20, 0x40, 0x40, // 7
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+    0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
+    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+    0xE0, 0x90, 0x90, 0x90, 0xE0, // D
+    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+    0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+];
+
+/// Large 8x10 sprites for the hexadecimal digits, 10 bytes each.
+const BIG_FONT_DATA: [u8; 160] = [
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
+
+/// What happened when executing an instruction with [`Chip8::step`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepOutcome {
+    Continue,
+    /// The display was drawn to.
+    Draw,
+    /// The whole display was cleared, so that it's blank. Frontends can blank their output
+    /// without looking at the framebuffer.
+    ClearScreen,
+    /// Blocked on `FX0A` waiting for a key to store in register VX.
+    WaitingForKey(u8),
+    /// Nothing was executed, since with the display wait quirk the program is waiting for the
+    /// timers to tick after drawing.
+    WaitingForFrame,
+    /// The program has finished and there is nothing left to execute.
+    Halt,
+}
+
+/// An error raised by the program running on the interpreter, or when loading a ROM or restoring
+/// a snapshot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chip8Error {
+    /// The instruction `opcode` at `pc` isn't one we know how to execute. It's left skipped, so
+    /// stepping again carries on with the next instruction.
+    UnknownOpcode { pc: u16, opcode: u16 },
+    /// Returned from a subroutine with nothing on the stack.
+    StackUnderflow { pc: u16 },
+    /// Called a subroutine with the stack already full.
+    StackOverflow { pc: u16 },
+    /// The instruction at `pc` accessed memory at `addr`, beyond the end of memory.
+    AddressOutOfBounds { pc: u16, addr: u32 },
+    /// A ROM of `len` bytes was too large to load, since only `max` bytes fit above the load
+    /// address.
+    RomTooLarge { len: usize, max: usize },
+    /// A snapshot passed to [`Chip8::load_state`] couldn't be restored.
+    InvalidState(&'static str),
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownOpcode { pc, opcode } => {
+                write!(f, "unknown opcode {opcode:04X} at {pc:#05X}")
+            }
+            Self::StackUnderflow { pc } => write!(f, "return with empty stack at {pc:#05X}"),
+            Self::StackOverflow { pc } => write!(f, "call with full stack at {pc:#05X}"),
+            Self::AddressOutOfBounds { pc, addr } => {
+                write!(f, "out of bounds memory access at {addr:#X} by {pc:#05X}")
+            }
+            Self::RomTooLarge { len, max } => {
+                write!(
+                    f,
+                    "ROM is {len} bytes but at most {max} bytes fit in memory"
+                )
+            }
+            Self::InvalidState(reason) => write!(f, "invalid snapshot: {reason}"),
+        }
+    }
+}
+
+impl core::error::Error for Chip8Error {}
+
+/// Something suspicious done by the program which isn't serious enough to stop it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Warning {
+    /// The instruction at `pc` drew a sprite from `addr` which ran off the end of memory and wrapped
+    /// around to the start.
+    SpriteWrapped { pc: u16, addr: u16 },
+    /// The instruction at `pc` loaded or stored bytes from `addr` which ran off the end of memory
+    /// and wrapped around to the start.
+    MemoryWrapped { pc: u16, addr: u16 },
+    /// The instruction at `pc` wrote over the instruction at `addr`, which was one of the last
+    /// few executed. Programs rarely modify their own code on purpose, so this is usually a stray
+    /// `FX33` or `FX55`.
+    CodeOverwritten { pc: u16, addr: u16 },
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::SpriteWrapped { pc, addr } => {
+                write!(
+                    f,
+                    "sprite at {addr:#05X} drawn by {pc:#05X} wraps past the end of memory"
+                )
+            }
+            Self::MemoryWrapped { pc, addr } => {
+                write!(
+                    f,
+                    "memory at {addr:#05X} accessed by {pc:#05X} wraps past the end of memory"
+                )
+            }
+            Self::CodeOverwritten { pc, addr } => {
+                write!(
+                    f,
+                    "{pc:#05X} overwrote the instruction at {addr:#05X}, which was just executed"
+                )
+            }
+        }
+    }
+}
+
+/// The delay and sound timers, which count down to zero at 60Hz.
+///
+/// Each tick decrements both timers, exactly once per call to [`Timers::tick`], and
+/// between ticks they only change when written. So `FX15` followed by `FX07` within a frame reads
+/// back the value written, and a frame later reads one less. The host decides where the 60Hz
+/// boundaries fall: ticking on the same thread as [`Chip8::step`], as [`Chip8::run_frame`] does,
+/// puts every tick between two instructions. The timers are atomic so that they can also be
+/// ticked from a thread of their own, at a steady rate however fast instructions are executed, but
+/// then a tick can land at any point relative to an instruction.
+#[derive(Debug, Default)]
+pub struct Timers {
+    delay: AtomicU8,
+    sound: AtomicU8,
+    /// Whether the timers have ticked since this was last cleared, marking the start of a frame.
+    ticked: AtomicBool,
+    on_sound: Lock<Option<SoundHook>>,
+}
+
+/// Called with whether the beep should sound whenever that changes, see [`Chip8::on_sound`].
+struct SoundHook(Box<dyn FnMut(bool) + Send>);
+
+impl fmt::Debug for SoundHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SoundHook")
+    }
+}
+
+impl Timers {
+    /// Decrement both timers unless they're already zero. Should be called at 60Hz.
+    pub fn tick(&self) {
+        let decrement = |t: u8| t.checked_sub(1);
+        let _ = self
+            .delay
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, decrement);
+        self.update_sound(decrement);
+        self.ticked.store(true, Ordering::Relaxed);
+    }
+
+    pub fn delay(&self) -> u8 {
+        self.delay.load(Ordering::Relaxed)
+    }
+
+    pub fn sound(&self) -> u8 {
+        self.sound.load(Ordering::Relaxed)
+    }
+
+    /// Whether the timers have ticked since the last call.
+    fn take_tick(&self) -> bool {
+        self.ticked.swap(false, Ordering::Relaxed)
+    }
+
+    fn set_delay(&self, value: u8) {
+        self.delay.store(value, Ordering::Relaxed);
+    }
+
+    fn set_sound(&self, value: u8) {
+        self.update_sound(|_| Some(value));
+    }
+
+    /// Update the sound timer with `f`, as in `fetch_update`, calling the hook if the beep starts
+    /// or stops. The update and the call happen under the hook's lock, so that when `tick` and
+    /// `set_sound` race on different threads the hook still hears about the changes in the order
+    /// they were made, ending up in the same state as the timer.
+    fn update_sound(&self, f: impl FnMut(u8) -> Option<u8>) {
+        self.on_sound.with(|hook| {
+            let Ok(old) = self
+                .sound
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, f)
+            else {
+                return;
+            };
+            let sounding = self.sound.load(Ordering::Relaxed) > 0;
+            if let Some(SoundHook(hook)) = hook {
+                if (old > 0) != sounding {
+                    hook(sounding);
+                }
+            }
+        });
+    }
+}
+
+/// An XO-CHIP audio pattern, played instead of the beep while the sound timer is nonzero.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AudioPattern {
+    /// 128 one-bit samples, most significant bit first, loaded by `F002`.
+    pub bits: [u8; 16],
+    /// Playback rate set by `FX3A`, see [`AudioPattern::rate`].
+    pub pitch: u8,
+}
+
+impl AudioPattern {
+    /// Pitch until the program sets one.
+    pub const DEFAULT_PITCH: u8 = 64;
+
+    /// Samples played per second, 4000 at the default pitch and doubling every 48 above it. Only
+    /// with `std`, which has the floating point maths for it.
+    #[cfg(feature = "std")]
+    pub fn rate(&self) -> f64 {
+        4000.0 * 2f64.powf((self.pitch as f64 - 64.0) / 48.0)
+    }
+
+    /// Whether sample `i` of the pattern, which repeats every 128 samples, is high.
+    pub fn sample(&self, i: usize) -> bool {
+        let i = i % 128;
+        self.bits[i / 8] >> (7 - i % 8) & 1 == 1
+    }
+}
+
+/// Registers and memory to start a machine with.
+///
+/// This sets up a test of a single instruction without running a ROM. Fields left at their
+/// defaults match a new machine, so only what the test cares about needs giving:
+///
+/// ```
+/// # use chip8::{Chip8, InitialState, Quirks};
+/// let mut chip8 = Chip8::with_state(
+///     Quirks::default(),
+///     InitialState {
+///         registers: [0x12; 16],
+///         memory: vec![(0x200, vec![0x80, 0x14])], // ADD V0, V1
+///         ..InitialState::default()
+///     },
+/// );
+/// chip8.step().unwrap();
+/// assert_eq!(chip8.registers()[0], 0x24);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InitialState {
+    /// V0-VF.
+    pub registers: [u8; 16],
+    /// The index register I.
+    pub index: u16,
+    /// The program counter, or the load address if `None`.
+    pub pc: Option<u16>,
+    /// Runs of bytes to write into memory, each at the address paired with it.
+    pub memory: Vec<(u16, Vec<u8>)>,
+}
+
+/// The state of a CHIP-8 machine.
+#[derive(Debug)]
+pub struct Chip8 {
+    /// As much memory as `quirks.memory_size`.
+    memory: Box<[u8]>,
+    display: Display,
+    /// The planes drawn to, as a mask of the bits in each pixel (XO-CHIP).
+    planes: u8,
+    pc: u16,
+    ri: u16,
+    timers: Arc<Timers>,
+    rv: [u8; 16],
+    stack: Vec<u16>,
+    keys: [bool; 16],
+    /// Keys seen held down while blocked on `FX0A`.
+    held_keys: [bool; 16],
+    quirks: Quirks,
+    prng: Lfsr,
+    /// SUPER-CHIP RPL user flags, saved and restored by `FX75` and `FX85`.
+    rpl: [u8; 8],
+    /// The XO-CHIP audio pattern, or `None` for the plain beep until one is loaded.
+    audio: Option<[u8; 16]>,
+    pitch: u8,
+    /// The loaded ROM, kept so that the machine can be reset.
+    rom: Vec<u8>,
+    warning: Option<Warning>,
+    /// The addresses of the last instructions fetched, oldest overwritten first, to notice the
+    /// program writing over code it has just executed.
+    recent_fetches: [Option<u16>; RECENT_FETCHES],
+    next_fetch: usize,
+    /// Whether the program has drawn and, with the display wait quirk, is waiting for the next
+    /// frame.
+    waiting_for_frame: bool,
+}
+
+impl Chip8 {
+    /// A machine which behaves according to `quirks`.
+    pub fn new(quirks: Quirks) -> Self {
+        let mut chip8 = Self {
+            memory: vec![0; quirks.memory_size].into_boxed_slice(),
+            display: Display::default(),
+            planes: 1,
+            pc: quirks.load_address as u16,
+            ri: 0x0,
+            timers: Arc::default(),
+            rv: [0; 16],
+            stack: Vec::with_capacity(quirks.stack_depth),
+            keys: [false; 16],
+            held_keys: [false; 16],
+            quirks,
+            prng: Lfsr::with_seed(Lfsr::DEFAULT_SEED),
+            rpl: [0; 8],
+            audio: None,
+            pitch: AudioPattern::DEFAULT_PITCH,
+            rom: Vec::new(),
+            warning: None,
+            recent_fetches: [None; RECENT_FETCHES],
+            next_fetch: 0,
+            waiting_for_frame: false,
+        };
+        chip8.load_font();
+        chip8
+    }
+
+    /// A machine which behaves according to `quirks`, with the registers and memory in `state`.
+    ///
+    /// # Panics
+    ///
+    /// If any of the bytes to write don't fit in memory.
+    pub fn with_state(quirks: Quirks, state: InitialState) -> Self {
+        let mut chip8 = Self::new(quirks);
+        chip8.rv = state.registers;
+        chip8.ri = state.index;
+        if let Some(pc) = state.pc {
+            chip8.pc = pc;
+        }
+        for (addr, bytes) in state.memory {
+            let addr = addr as usize;
+            chip8
+                .memory
+                .get_mut(addr..addr + bytes.len())
+                .expect("initial memory fits")
+                .copy_from_slice(&bytes);
+        }
+        chip8
+    }
+
+    fn load_font(&mut self) {
+        self.memory[FONT_BASE..FONT_BASE + FONT_DATA.len()].copy_from_slice(&FONT_DATA);
+        self.memory[BIG_FONT_BASE..BIG_FONT_BASE + BIG_FONT_DATA.len()]
+            .copy_from_slice(&BIG_FONT_DATA);
+    }
+
+    /// Load `rom` into memory at the load address, swapping the bytes of each word if
+    /// [`Quirks::byte_swap`] is set. Fails without changing memory if it doesn't fit below the top
+    /// of memory.
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), Chip8Error> {
+        let start = self.quirks.load_address;
+        let max = self.memory.len() - start;
+        if rom.len() > max {
+            return Err(Chip8Error::RomTooLarge {
+                len: rom.len(),
+                max,
+            });
+        }
+        let program = &mut self.memory[start..start + rom.len()];
+        program.copy_from_slice(rom);
+        if self.quirks.byte_swap {
+            for word in program.chunks_exact_mut(2) {
+                word.swap(0, 1);
+            }
+        }
+        self.rom = rom.to_vec();
+        Ok(())
+    }
+
+    /// Overwrite the byte at `addr`, e.g. to patch the loaded program. Addresses past the end of
+    /// memory are ignored.
+    pub fn poke(&mut self, addr: u16, value: u8) {
+        if let Some(byte) = self.memory.get_mut(addr as usize) {
+            *byte = value;
+        }
+    }
+
+    /// Restart the loaded ROM from the beginning, as if the machine had just been switched on. The
+    /// keypad, quirks and random number generator are left as they are.
+    pub fn reset(&mut self) {
+        self.memory.fill(0);
+        self.load_font();
+        let rom = core::mem::take(&mut self.rom);
+        self.load_rom(&rom).expect("the ROM fitted before");
+        self.display.resize(WIDTH, HEIGHT);
+        self.planes = 1;
+        self.pc = self.quirks.load_address as u16;
+        self.ri = 0;
+        self.timers.set_delay(0);
+        self.timers.set_sound(0);
+        self.rv = [0; 16];
+        self.stack.clear();
+        self.held_keys = [false; 16];
+        self.audio = None;
+        self.pitch = AudioPattern::DEFAULT_PITCH;
+        self.waiting_for_frame = false;
+        self.recent_fetches = [None; RECENT_FETCHES];
+    }
+
+    /// Restart the random number generator used by `CXNN` from `seed`. See [`Lfsr::with_seed`].
+    pub fn seed_prng(&mut self, seed: u8) {
+        self.prng = Lfsr::with_seed(seed);
+    }
+
+    /// Set whether keypad key `key` is held down.
+    pub fn set_key(&mut self, key: u8, pressed: bool) {
+        self.keys[key as usize & 0xf] = pressed;
+    }
+
+    /// Decrement the delay and sound timers. Should be called at 60Hz, either through this or
+    /// through the handle from [`Chip8::timers`].
+    pub fn tick_timers(&mut self) {
+        self.timers.tick();
+    }
+
+    /// Run a 60Hz frame: tick the timers, then execute `cycles` instructions or until the program
+    /// halts. Returns whether the display changed and so needs drawing again.
+    pub fn run_frame(&mut self, cycles: u32) -> Result<bool, Chip8Error> {
+        self.tick_timers();
+        let mut drawn = false;
+        for _ in 0..cycles {
+            match self.step()? {
+                StepOutcome::Draw | StepOutcome::ClearScreen => drawn = true,
+                StepOutcome::Halt | StepOutcome::WaitingForFrame => break,
+                StepOutcome::Continue | StepOutcome::WaitingForKey(_) => {}
+            }
+        }
+        Ok(drawn)
+    }
+
+    /// A handle to the timers, so that they can be ticked from another thread.
+    pub fn timers(&self) -> Arc<Timers> {
+        Arc::clone(&self.timers)
+    }
+
+    /// The beep should sound while this is nonzero.
+    pub fn sound_timer(&self) -> u8 {
+        self.timers.sound()
+    }
+
+    /// Call `hook` with `true` when the sound timer is set from zero, so the beep should start,
+    /// and with `false` when it reaches zero again. The hook runs on whichever thread changed the
+    /// timer, which may be the one ticking the [`Chip8::timers`] handle, and mustn't call back
+    /// into the timers. It replaces any hook set before.
+    pub fn on_sound(&mut self, hook: impl FnMut(bool) + Send + 'static) {
+        self.timers
+            .on_sound
+            .with(|on_sound| *on_sound = Some(SoundHook(Box::new(hook))));
+    }
+
+    /// The XO-CHIP audio pattern to play while the sound timer is nonzero, or `None` to play the
+    /// usual beep.
+    pub fn audio_pattern(&self) -> Option<AudioPattern> {
+        self.audio.map(|bits| AudioPattern {
+            bits,
+            pitch: self.pitch,
+        })
+    }
+
+    /// The pixels of the display, row by row, [`Chip8::dimensions`] in size. Each is 0 or 1, or for
+    /// XO-CHIP programs up to 3 with bit 0 set for the first plane and bit 1 for the second.
+    pub fn framebuffer(&self) -> &[u8] {
+        self.display.pixels()
+    }
+
+    /// The display, for frontends to draw.
+    pub fn display(&self) -> &Display {
+        &self.display
+    }
+
+    /// Width and height of the display in its current resolution.
+    pub fn dimensions(&self) -> (usize, usize) {
+        self.display.dimensions()
+    }
+
+    /// The font digits shown on the display, as their column, row and value, in reading order.
+    /// This is for reading the results of test ROMs, which often report them as digits drawn with
+    /// `FX29`. A digit only counts if it's surrounded by unlit pixels, and is matched against the
+    /// font in memory in case the program has changed it.
+    pub fn displayed_digits(&self) -> Vec<(usize, usize, u8)> {
+        let (width, height) = self.display.dimensions();
+        let lit = |x: usize, y: usize| self.display.get(x, y) != 0;
+        let font = &self.memory[FONT_BASE..FONT_BASE + FONT_DATA.len()];
+        let mut digits = Vec::new();
+        for y in 0..height.saturating_sub(4) {
+            for x in 0..width.saturating_sub(3) {
+                // The glyph itself, then the border around it which is on the display.
+                let glyph = font.chunks(5).position(|sprite| {
+                    (0..5)
+                        .all(|j| (0..4).all(|i| lit(x + i, y + j) == (sprite[j] << i & 0x80 != 0)))
+                });
+                let Some(digit) = glyph else {
+                    continue;
+                };
+                let border = (y.saturating_sub(1)..(y + 6).min(height)).all(|j| {
+                    (x.saturating_sub(1)..(x + 5).min(width))
+                        .all(|i| (x..x + 4).contains(&i) && (y..y + 5).contains(&j) || !lit(i, j))
+                });
+                if border {
+                    digits.push((x, y, digit as u8));
+                }
+            }
+        }
+        digits
+    }
+
+    /// Width of the display in its current resolution.
+    pub fn width(&self) -> usize {
+        self.display.width()
+    }
+
+    /// Height of the display in its current resolution.
+    pub fn height(&self) -> usize {
+        self.display.height()
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.memory[..]
+    }
+
+    /// The program counter.
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    /// The index register I.
+    pub fn index(&self) -> u16 {
+        self.ri
+    }
+
+    /// The registers V0-VF.
+    pub fn registers(&self) -> &[u8; 16] {
+        &self.rv
+    }
+
+    /// Return addresses of the subroutines currently being executed, innermost last.
+    pub fn stack(&self) -> &[u16] {
+        &self.stack
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.timers.delay()
+    }
+
+    /// The last warning raised by an instruction, if it hasn't been taken already.
+    pub fn take_warning(&mut self) -> Option<Warning> {
+        self.warning.take()
+    }
+
+    /// Warn if the `len` bytes from `start` just written by the instruction at `pc` overlap a
+    /// recently executed instruction.
+    fn check_overwritten(&mut self, pc: u16, start: usize, len: usize) {
+        let overwritten = self.recent_fetches.iter().flatten().find(|&&addr| {
+            let addr = addr as usize;
+            addr < start + len && start < addr + 2
+        });
+        if let Some(&addr) = overwritten {
+            self.warning = Some(Warning::CodeOverwritten { pc, addr });
+        }
+    }
+
+    /// Fetch, decode and execute a single instruction.
+    pub fn step(&mut self) -> Result<StepOutcome, Chip8Error> {
+        if self.waiting_for_frame {
+            if !self.timers.take_tick() {
+                return Ok(StepOutcome::WaitingForFrame);
+            }
+            self.waiting_for_frame = false;
+        }
+
+        // Fetch. Running off the end of memory is an error, except that with 64KB of memory the PC
+        // wraps around to 0 like XO-CHIP's 16 bit PC.
+        let pc = self.pc;
+        let addr = self.check_address(pc, pc as u32 + 1)? - 1;
+        let current_instruction = u16::from_be_bytes([self.memory[addr], self.memory[addr + 1]]);
+        self.pc = pc.wrapping_add(2);
+        self.recent_fetches[self.next_fetch] = Some(pc);
+        self.next_fetch = (self.next_fetch + 1) % RECENT_FETCHES;
+
+        /// Index by nibble i from some the current instruction.
+        /// e.g. i=0123
+        ///      0xFFFF
+        macro_rules! nibble {
+            ($i:expr) => {
+                current_instruction as usize >> (4 * (3 - $i)) & 0xf
+            };
+        }
+        macro_rules! rv {
+            (X) => {
+                self.rv[nibble!(1)]
+            };
+            (Y) => {
+                self.rv[nibble!(2)]
+            };
+        }
+
+        let mut outcome = StepOutcome::Continue;
+        let unknown = Chip8Error::UnknownOpcode {
+            pc,
+            opcode: current_instruction,
+        };
+
+        // Decode + Execute
+        match current_instruction >> 12 & 0xf {
+            0x0 => match current_instruction {
+                // Clear screen.
+                0x00E0 => {
+                    // Only the selected planes are cleared (XO-CHIP), which may leave pixels lit
+                    // in the others.
+                    self.display.clear(self.planes);
+                    outcome = if self.display.is_blank() {
+                        StepOutcome::ClearScreen
+                    } else {
+                        StepOutcome::Draw
+                    };
+                }
+                // Switch to low resolution (SUPER-CHIP), clearing the screen.
+                0x00FE => {
+                    self.display.resize(WIDTH, HEIGHT);
+                    outcome = StepOutcome::ClearScreen;
+                }
+                // Switch to high resolution (SUPER-CHIP), clearing the screen.
+                0x00FF => {
+                    self.display.resize(HIRES_WIDTH, HIRES_HEIGHT);
+                    outcome = StepOutcome::ClearScreen;
+                }
+                // Scroll down N pixels (SUPER-CHIP).
+                0x00C0..=0x00CF => {
+                    self.display.scroll_down(nibble!(3), self.planes);
+                    outcome = StepOutcome::Draw;
+                }
+                // Scroll right 4 pixels (SUPER-CHIP).
+                0x00FB => {
+                    self.display.scroll_right(4, self.planes);
+                    outcome = StepOutcome::Draw;
+                }
+                // Scroll left 4 pixels (SUPER-CHIP).
+                0x00FC => {
+                    self.display.scroll_left(4, self.planes);
+                    outcome = StepOutcome::Draw;
+                }
+                // Exit (SUPER-CHIP). Stay on this instruction so the machine remains halted.
+                0x00FD => {
+                    self.pc = pc;
+                    outcome = StepOutcome::Halt;
+                }
+                // Return from subroutine.
+                0x00EE => self.pc = self.stack.pop().ok_or(Chip8Error::StackUnderflow { pc })?,
+                _ => return Err(unknown),
+            },
+            // Jump to NNN immediate. Jumping to the jump itself is a common way to end a program,
+            // so report that as halting.
+            0x1 => {
+                self.pc = current_instruction & 0x0fff;
+                if self.pc == pc {
+                    outcome = StepOutcome::Halt;
+                }
+            }
+            // Call subroutine at NNN.
+            0x2 => {
+                if self.stack.len() >= self.quirks.stack_depth {
+                    return Err(Chip8Error::StackOverflow { pc });
+                }
+                self.stack.push(self.pc);
+                self.pc = current_instruction & 0x0fff;
+            }
+            // Skip if VX == NN.
+            0x3 => {
+                if self.rv[nibble!(1)] == current_instruction as u8 {
+                    self.skip();
+                }
+            }
+            // Skip if VX != NN.
+            0x4 => {
+                if self.rv[nibble!(1)] != current_instruction as u8 {
+                    self.skip();
+                }
+            }
+            // Skip if VX == VY.
+            0x5 => {
+                if self.rv[nibble!(1)] == self.rv[nibble!(2)] {
+                    self.skip();
+                }
+            }
+            // Set register VX to NN.
+            0x6 => self.rv[nibble!(1)] = current_instruction as u8,
+            // Add to register VX value NN.
+            0x7 => {
+                let rv = &mut self.rv[nibble!(1)];
+                *rv = rv.wrapping_add(current_instruction as u8);
+            }
+            0x8 => match current_instruction & 0xf {
+                // Set VX to VY.
+                0x0 => self.rv[nibble!(1)] = self.rv[nibble!(2)],
+                // Set VX = VX | VY.
+                0x1 => {
+                    self.rv[nibble!(1)] |= self.rv[nibble!(2)];
+                    if self.quirks.vf_reset {
+                        self.rv[0xF] = 0;
+                    }
+                }
+                // Set VX = VX & VY.
+                0x2 => {
+                    self.rv[nibble!(1)] &= self.rv[nibble!(2)];
+                    if self.quirks.vf_reset {
+                        self.rv[0xF] = 0;
+                    }
+                }
+                // Set VX = VX xor VY.
+                0x3 => {
+                    self.rv[nibble!(1)] ^= self.rv[nibble!(2)];
+                    if self.quirks.vf_reset {
+                        self.rv[0xF] = 0;
+                    }
+                }
+                // Set VX = VX + VY and set carry in VF. VF is written last so that the carry wins
+                // when X is F.
+                0x4 => {
+                    let (sum, carry) = rv!(X).overflowing_add(rv!(Y));
+                    rv!(X) = sum;
+                    self.rv[0xF] = carry as u8;
+                }
+                // Set VX = VX - VY and set VF to 1 if there was no borrow, after the result as in
+                // 8XY4.
+                0x5 => {
+                    let (difference, borrow) = rv!(X).overflowing_sub(rv!(Y));
+                    rv!(X) = difference;
+                    self.rv[0xF] = !borrow as u8;
+                }
+                // VX = VY >> 1, or VX >> 1 with the quirk.
+                0x6 => {
+                    let x = if self.quirks.shift { rv!(X) } else { rv!(Y) };
+                    rv!(X) = x / 2;
+                    self.rv[0xF] = x % 2;
+                }
+                // Set VX = VY - VX and set VF to 1 if there was no borrow, after the result.
+                0x7 => {
+                    let (difference, borrow) = rv!(Y).overflowing_sub(rv!(X));
+                    rv!(X) = difference;
+                    self.rv[0xF] = !borrow as u8;
+                }
+                // VX = VY << 1, or VX << 1 with the quirk.
+                0xE => {
+                    let x = if self.quirks.shift { rv!(X) } else { rv!(Y) };
+                    rv!(X) = x << 1;
+                    self.rv[0xF] = if x & 0b1000_0000 > 0 { 1 } else { 0 };
+                }
+                _ => return Err(unknown),
+            },
+            // Skip if VX != VY.
+            0x9 => {
+                if self.rv[nibble!(1)] != self.rv[nibble!(2)] {
+                    self.skip();
+                }
+            }
+            // Set RI to NNN.
+            0xA => self.ri = current_instruction & 0x0fff,
+            // Jump to V0 + NNN, or VX + XNN with the quirk.
+            0xB => {
+                let offset = if self.quirks.jump_vx {
+                    rv!(X)
+                } else {
+                    self.rv[0]
+                };
+                self.pc = offset as u16 + (current_instruction & 0x0fff);
+            }
+            // VX = PRNG & NN.
+            0xC => rv!(X) = self.prng.next_byte() & current_instruction as u8,
+            // Draw DXYN, or a 16x16 sprite for DXY0 (SUPER-CHIP). Like Octo we draw big sprites in
+            // low resolution too, where CHIP-8 would draw nothing. With both XO-CHIP planes
+            // selected, the sprite for the second plane follows the one for the first in memory.
+            0xD => {
+                let (width, height) = self.display.dimensions();
+                let x = self.rv[nibble!(1)] as usize % width;
+                let y = self.rv[nibble!(2)] as usize % height;
+                let (rows, row_bytes) = match nibble!(3) {
+                    0 => (16, 2),
+                    n => (n, 1),
+                };
+                // Sprites running off the end of memory wrap around to the start, as addresses
+                // do on the COSMAC VIP.
+                let sprite_len = rows * row_bytes;
+                let planes = [1, 2].into_iter().filter(|&p| self.planes & p != 0);
+                let len = self.memory.len();
+                if self.ri as usize + sprite_len * planes.clone().count() > len {
+                    self.warning = Some(Warning::SpriteWrapped { pc, addr: self.ri });
+                }
+                self.rv[0xF] = 0;
+
+                for (start, plane) in (self.ri as usize..).step_by(sprite_len).zip(planes) {
+                    for (r, j) in (y..y + rows).enumerate() {
+                        if j >= height && !self.quirks.wrap_sprites {
+                            break;
+                        }
+                        // Left-align the row in 16 bits.
+                        let addr = start + r * row_bytes;
+                        let row = if row_bytes == 2 {
+                            u16::from_be_bytes([
+                                self.read_mem(addr as u16),
+                                self.read_mem((addr + 1) as u16),
+                            ])
+                        } else {
+                            (self.read_mem(addr as u16) as u16) << 8
+                        };
+                        for (i, x) in (0..8 * row_bytes).zip(x..) {
+                            if x >= width && !self.quirks.wrap_sprites {
+                                break;
+                            }
+                            if row >> (15 - i) & 0x1 == 0 {
+                                continue;
+                            }
+                            // Set VF if a lit pixel is turned off.
+                            if self.display.xor(x % width, j % height, plane) {
+                                self.rv[0xF] = 1;
+                            }
+                        }
+                    }
+                }
+                // The COSMAC VIP waits for the vertical blank interrupt before drawing, so draws at
+                // most once a frame.
+                if self.quirks.display_wait {
+                    self.timers.take_tick();
+                    self.waiting_for_frame = true;
+                }
+                outcome = StepOutcome::Draw;
+            }
+            0xE => match current_instruction as u8 {
+                // Skip if key VX is pressed.
+                0x9E => {
+                    if self.keys[rv!(X) as usize & 0xf] {
+                        self.skip();
+                    }
+                }
+                // Skip if key VX is not pressed.
+                0xA1 => {
+                    if !self.keys[rv!(X) as usize & 0xf] {
+                        self.skip();
+                    }
+                }
+                _ => return Err(unknown),
+            },
+            0xF => match current_instruction as u8 {
+                // Load I with the 16 bit address in the following two bytes (XO-CHIP).
+                0x00 if nibble!(1) == 0 => {
+                    let addr = self.check_address(pc, pc as u32 + 3)? - 1;
+                    self.ri = u16::from_be_bytes([self.memory[addr], self.memory[addr + 1]]);
+                    self.pc = self.pc.wrapping_add(2);
+                }
+                // Select the planes drawn to by the mask N (XO-CHIP).
+                0x01 => self.planes = nibble!(1) as u8 & 0b11,
+                // Load the audio pattern from the 16 bytes at I (XO-CHIP).
+                0x02 if nibble!(1) == 0 => {
+                    self.check_wrapped(pc, 16);
+                    let mut bits = [0; 16];
+                    for (i, bit) in bits.iter_mut().enumerate() {
+                        *bit = self.read_mem(self.ri.wrapping_add(i as u16));
+                    }
+                    self.audio = Some(bits);
+                }
+                0x07 => rv!(X) = self.timers.delay(),
+                // Block until a key is pressed and released, storing it in VX. The timers keep
+                // ticking since we only rewind the PC and re-execute on the next cycle.
+                0x0A => match (0..16).find(|&k| self.held_keys[k] && !self.keys[k]) {
+                    Some(k) => {
+                        rv!(X) = k as u8;
+                        self.held_keys = [false; 16];
+                    }
+                    None => {
+                        for (held, pressed) in self.held_keys.iter_mut().zip(self.keys) {
+                            *held |= pressed;
+                        }
+                        self.pc = pc;
+                        outcome = StepOutcome::WaitingForKey(nibble!(1) as u8);
+                    }
+                },
+                0x15 => self.timers.set_delay(rv!(X)),
+                0x18 => self.timers.set_sound(rv!(X)),
+                // Add VX to I, optionally setting VF on overflow out of the address space.
+                0x1E => {
+                    self.ri = self.ri.wrapping_add(rv!(X) as u16);
+                    if self.quirks.index_overflow {
+                        self.rv[0xF] = if self.ri >= 0x1000 { 1 } else { 0 };
+                        self.ri %= 0x1000;
+                    }
+                }
+                // Set the audio pattern's pitch to VX (XO-CHIP).
+                0x3A => self.pitch = rv!(X),
+                // Point I at the font sprite for the low nibble of VX.
+                0x29 => self.ri = FONT_BASE as u16 + (rv!(X) & 0xF) as u16 * 5,
+                // Point I at the large font sprite for the low nibble of VX (SUPER-CHIP).
+                0x30 => self.ri = BIG_FONT_BASE as u16 + (rv!(X) & 0xF) as u16 * 10,
+                // Store the binary-coded decimal of VX at I, I+1 and I+2.
+                0x33 => {
+                    self.check_wrapped(pc, 3);
+                    let x = rv!(X);
+                    for (i, digit) in [x / 100, x / 10 % 10, x % 10].into_iter().enumerate() {
+                        self.write_mem(self.ri.wrapping_add(i as u16), digit);
+                    }
+                    self.check_overwritten(pc, self.ri as usize, 3);
+                }
+                // Store V0..=VX in memory starting at I.
+                0x55 => {
+                    self.check_wrapped(pc, nibble!(1) + 1);
+                    for x in 0..=nibble!(1) {
+                        self.write_mem(self.ri.wrapping_add(x as u16), self.rv[x]);
+                    }
+                    self.check_overwritten(pc, self.ri as usize, nibble!(1) + 1);
+                    if self.quirks.memory_increment {
+                        self.ri = self.ri.wrapping_add(nibble!(1) as u16 + 1);
+                    }
+                }
+                // Load V0..=VX from memory starting at I.
+                0x65 => {
+                    self.check_wrapped(pc, nibble!(1) + 1);
+                    for x in 0..=nibble!(1) {
+                        self.rv[x] = self.read_mem(self.ri.wrapping_add(x as u16));
+                    }
+                    if self.quirks.memory_increment {
+                        self.ri = self.ri.wrapping_add(nibble!(1) as u16 + 1);
+                    }
+                }
+                // Save V0-VX in the RPL flags, for X up to 7 (SUPER-CHIP).
+                0x75 => {
+                    let x = nibble!(1).min(7);
+                    self.rpl[..=x].copy_from_slice(&self.rv[..=x]);
+                }
+                // Restore V0-VX from the RPL flags, for X up to 7 (SUPER-CHIP).
+                0x85 => {
+                    let x = nibble!(1).min(7);
+                    self.rv[..=x].copy_from_slice(&self.rpl[..=x]);
+                }
+                _ => return Err(unknown),
+            },
+            _ => return Err(unknown),
+        }
+
+        Ok(outcome)
+    }
+
+    /// Skip the next instruction, which is 4 bytes long if it's `F000 NNNN` (XO-CHIP).
+    fn skip(&mut self) {
+        let next = self.pc as usize;
+        let long = self.memory.get(next..next + 2) == Some(&[0xF0, 0x00]);
+        self.pc = self.pc.wrapping_add(if long { 4 } else { 2 });
+    }
+
+    /// The byte at `addr`, wrapping around past the end of memory as addresses do on the COSMAC
+    /// VIP, so that I can't point outside it.
+    fn read_mem(&self, addr: u16) -> u8 {
+        self.memory[addr as usize % self.memory.len()]
+    }
+
+    /// Set the byte at `addr`, wrapping around like [`Chip8::read_mem`].
+    fn write_mem(&mut self, addr: u16, value: u8) {
+        let len = self.memory.len();
+        self.memory[addr as usize % len] = value;
+    }
+
+    /// Warn if the `len` bytes at I accessed by the instruction at `pc` wrap past the end of
+    /// memory.
+    fn check_wrapped(&mut self, pc: u16, len: usize) {
+        if self.ri as usize + len > self.memory.len() {
+            self.warning = Some(Warning::MemoryWrapped { pc, addr: self.ri });
+        }
+    }
+
+    /// Check that the instruction at `pc` may access `addr`, returning it as an index into memory.
+    fn check_address(&self, pc: u16, addr: u32) -> Result<usize, Chip8Error> {
+        if (addr as usize) < self.memory.len() {
+            Ok(addr as usize)
+        } else {
+            Err(Chip8Error::AddressOutOfBounds { pc, addr })
+        }
+    }
+}
+
+/// Render `pixels`, `width` pixels wide, as text with one line per row, `#` for a lit pixel and
+/// `.` for an unlit one.
+pub fn framebuffer_to_text(pixels: &[u8], width: usize) -> String {
+    let mut text = String::with_capacity(pixels.len() + pixels.len() / width);
+    for row in pixels.chunks(width) {
+        text.extend(row.iter().map(|&p| if p == 0 { '.' } else { '#' }));
+        text.push('\n');
+    }
+    text
+}
+
+/// Run `rom` without any frontend for `cycles` instructions, or until it halts, ticking the timers
+/// at 60Hz as if running at [`DEFAULT_HZ`]. Returns the machine so its display can be inspected.
+pub fn run_headless(rom: &[u8], cycles: usize) -> Result<Chip8, Chip8Error> {
+    let mut chip8 = Chip8::new(Quirks::default());
+    chip8.load_rom(rom)?;
+    run_cycles(&mut chip8, cycles)?;
+    Ok(chip8)
+}
+
+/// Run the loaded program as [`run_headless`] does.
+fn run_cycles(chip8: &mut Chip8, cycles: usize) -> Result<(), Chip8Error> {
+    let hz = DEFAULT_HZ as usize;
+    for cycle in 0..cycles {
+        if chip8.step()? == StepOutcome::Halt {
+            break;
+        }
+        if (cycle + 1) * 60 / hz > cycle * 60 / hz {
+            chip8.tick_timers();
+        }
+    }
+    Ok(())
+}
+
+impl Default for Chip8 {
+    fn default() -> Self {
+        Self::new(Quirks::default())
+    }
+}
+
+// To avoid bringing in rand, simple PRNG implementation using LSFR.
+// <https://en.wikipedia.org/wiki/Linear-feedback_shift_register>
+#[derive(Debug)]
+pub struct Lfsr(u8);
+impl Lfsr {
+    pub const DEFAULT_SEED: u8 = 0xFF;
+
+    /// An LFSR starting from `seed`. The all-zeros state can never be left, so a seed of 0 is
+    /// replaced with [`Lfsr::DEFAULT_SEED`].
+    pub fn with_seed(seed: u8) -> Self {
+        Self(if seed == 0 { Self::DEFAULT_SEED } else { seed })
+    }
+
+    // 10110100
+    pub fn next_byte(&mut self) -> u8 {
+        let bit = (self.0 >> 7) ^ (self.0 >> 5) ^ (self.0 >> 4) ^ (self.0 >> 2);
+        self.0 = (bit << 7) | (self.0 >> 1);
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    /// Run the given instructions from 0x200 on `chip8`, one step per instruction. Drawing doesn't
+    /// wait for the next frame.
+    fn run(chip8: &mut Chip8, instructions: &[u16]) {
+        let rom: Vec<u8> = instructions.iter().flat_map(|i| i.to_be_bytes()).collect();
+        chip8.load_rom(&rom).unwrap();
+        for _ in instructions {
+            chip8.waiting_for_frame = false;
+            chip8.step().unwrap();
+        }
+    }
+
+    #[test]
+    fn init_memory() {
+        drop(super::Chip8::new(Quirks::default()));
+    }
+
+    #[test]
+    fn step_outcome() {
+        let mut chip8 = Chip8::new(Quirks::default());
+        chip8
+            .load_rom(&[0x00, 0xE0, 0xD0, 0x01, 0x63, 0x00, 0xF3, 0x0A])
+            .unwrap();
+        assert_eq!(chip8.step(), Ok(StepOutcome::ClearScreen));
+        assert_eq!(chip8.step(), Ok(StepOutcome::Draw));
+        assert_eq!(chip8.step(), Ok(StepOutcome::WaitingForFrame));
+        chip8.tick_timers();
+        assert_eq!(chip8.step(), Ok(StepOutcome::Continue));
+        assert_eq!(chip8.step(), Ok(StepOutcome::WaitingForKey(3)));
+        assert_eq!(chip8.step(), Ok(StepOutcome::WaitingForKey(3)));
+    }
+
+    #[test]
+    fn stack_overflow() {
+        // A subroutine which calls itself forever.
+        for depth in [16, 2] {
+            let mut chip8 = Chip8::new(Quirks {
+                stack_depth: depth,
+                ..Quirks::default()
+            });
+            chip8.load_rom(&[0x22, 0x00]).unwrap();
+            for _ in 0..depth {
+                assert_eq!(chip8.step(), Ok(StepOutcome::Continue));
+            }
+            assert_eq!(chip8.step(), Err(Chip8Error::StackOverflow { pc: 0x200 }));
+            assert_eq!(chip8.stack.len(), depth);
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let mut chip8 = Chip8::new(Quirks::default());
+        chip8.load_rom(&[0x00, 0xEE]).unwrap();
+        assert_eq!(chip8.step(), Err(Chip8Error::StackUnderflow { pc: 0x200 }));
+
+        let mut chip8 = Chip8::new(Quirks::default());
+        chip8.load_rom(&[0x80, 0x0F]).unwrap();
+        assert_eq!(
+            chip8.step(),
+            Err(Chip8Error::UnknownOpcode {
+                pc: 0x200,
+                opcode: 0x800F
+            })
+        );
+        assert_eq!(chip8.pc, 0x202);
+    }
+
+    #[test]
+    fn memory_wraps() {
+        // The digits of 123 stored at the end of memory, with the last one wrapping to the start.
+        let mut chip8 = Chip8::new(Quirks::default());
+        chip8.rv[0] = 123;
+        chip8.ri = 0xFFE;
+        run(&mut chip8, &[0xF033]);
+        assert_eq!(
+            [chip8.memory[0xFFE], chip8.memory[0xFFF], chip8.memory[0]],
+            [1, 2, 3]
+        );
+        assert_eq!(
+            chip8.take_warning(),
+            Some(Warning::MemoryWrapped {
+                pc: 0x200,
+                addr: 0xFFE
+            })
+        );
+
+        // I beyond 4KB is masked into memory, and loads wrap too.
+        chip8.pc = 0x200;
+        chip8.ri = 0x1FFF;
+        run(&mut chip8, &[0xF165]);
+        assert_eq!(chip8.rv[..2], [2, 3]);
+        assert!(chip8.take_warning().is_some());
+        chip8.pc = 0x200;
+        chip8.ri = 0x300;
+        run(&mut chip8, &[0xF165]);
+        assert_eq!(chip8.take_warning(), None);
+    }
+
+    #[test]
+    fn pc_out_of_bounds() {
+        // Falling through the last instruction in memory.
+        let mut chip8 = Chip8::new(Quirks::default());
+        chip8.poke(0xFFE, 0x60);
+        chip8.pc = 0xFFE;
+        assert_eq!(chip8.step(), Ok(StepOutcome::Continue));
+        assert_eq!(
+            chip8.step(),
+            Err(Chip8Error::AddressOutOfBounds {
+                pc: 0x1000,
+                addr: 0x1001
+            })
+        );
+
+        // An instruction straddling the end of memory, and a long load of I running past it.
+        chip8.pc = 0xFFF;
+        assert_eq!(
+            chip8.step(),
+            Err(Chip8Error::AddressOutOfBounds {
+                pc: 0xFFF,
+                addr: 0x1000
+            })
+        );
+        chip8.poke(0xFFE, 0xF0);
+        chip8.poke(0xFFF, 0x00);
+        chip8.pc = 0xFFE;
+        assert_eq!(
+            chip8.step(),
+            Err(Chip8Error::AddressOutOfBounds {
+                pc: 0xFFE,
+                addr: 0x1001
+            })
+        );
+
+        // With 64KB of memory the PC wraps around.
+        let mut chip8 = Chip8::new(Quirks::xochip());
+        chip8.poke(0xFFFE, 0x60);
+        chip8.pc = 0xFFFE;
+        assert_eq!(chip8.step(), Ok(StepOutcome::Continue));
+        assert_eq!(chip8.pc, 0);
+    }
+
+    #[test]
+    fn random() {
+        let mut chip8 = Chip8::new(Quirks::default());
+        chip8.seed_prng(0x5A);
+        run(&mut chip8, &[0xC0FF, 0xC10F]);
+        assert_eq!(chip8.rv[..2], [0xAD, 0x06]);
+
+        let mut lfsr = Lfsr::with_seed(0x5A);
+        assert_eq!([lfsr.next_byte(), lfsr.next_byte() & 0x0F], [0xAD, 0x06]);
+        assert_eq!(Lfsr::with_seed(0).0, Lfsr::DEFAULT_SEED);
+    }
+
+    #[test]
+    fn bcd() {
+        let mut chip8 = Chip8::new(Quirks::default());
+        chip8.rv[3] = 254;
+        chip8.ri = 0x300;
+        run(&mut chip8, &[0xF333]);
+        assert_eq!(chip8.memory[0x300..0x303], [2, 5, 4]);
+    }
+
+    #[test]
+    fn with_state() {
+        // Load V0-V2 from I, in a program away from the load address.
+        let mut chip8 = Chip8::with_state(
+            Quirks::default(),
+            InitialState {
+                registers: [0xFF; 16],
+                index: 0x400,
+                pc: Some(0x300),
+                memory: vec![(0x300, vec![0xF2, 0x65]), (0x400, vec![1, 2, 3])],
+            },
+        );
+        assert_eq!(chip8.step(), Ok(StepOutcome::Continue));
+        assert_eq!(chip8.rv[..4], [1, 2, 3, 0xFF]);
+        assert_eq!(chip8.pc, 0x302);
+        // The font is there as usual.
+        assert_eq!(chip8.memory[FONT_BASE], FONT_DATA[0]);
+    }
+
+    #[test]
+    fn font_sprite() {
+        let mut chip8 = Chip8::new(Quirks::default());
+        chip8.rv[0] = 0xA;
+        run(&mut chip8, &[0xF029, 0xD115]);
+        let expected = [0xF0, 0x90, 0xF0, 0x90, 0x90];
+        for (y, row) in expected.into_iter().enumerate() {
+            for x in 0..8 {
+                assert_eq!(chip8.display.get(x, y), row >> (7 - x) & 1, "({x}, {y})");
+            }
+        }
+    }
+
+    #[test]
+    fn add_carry() {
+        let mut chip8 = Chip8::new(Quirks::default());
+        chip8.rv[1] = 0xF0;
+        chip8.rv[2] = 0x20;
+        run(&mut chip8, &[0x8124]);
+        assert_eq!((chip8.rv[1], chip8.rv[0xF]), (0x10, 1));
+
+        // With VF as the destination the flag overwrites the sum.
+        for (vf, carry) in [(0xF0, 1), (0x10, 0)] {
+            let mut chip8 = Chip8::new(Quirks::default());
+            chip8.rv[0xF] = vf;
+            chip8.rv[2] = 0x20;
+            run(&mut chip8, &[0x8F24]);
+            assert_eq!(chip8.rv[0xF], carry);
+        }
+    }
+
+    #[test]
+    fn sub_borrow() {
+        // (opcode, VX, VY, result, VF) with X = 1 and Y = 2.
+        for (opcode, x, y, result, vf) in [
+            (0x8125, 5, 3, 2, 1),
+            (0x8125, 3, 5, 0xFE, 0),
+            (0x8125, 4, 4, 0, 1),
+            (0x8127, 3, 5, 2, 1),
+            (0x8127, 5, 3, 0xFE, 0),
+            (0x8127, 4, 4, 0, 1),
+        ] {
+            let mut chip8 = Chip8::new(Quirks::default());
+            chip8.rv[1] = x;
+            chip8.rv[2] = y;
+            run(&mut chip8, &[opcode]);
+            assert_eq!((chip8.rv[1], chip8.rv[0xF]), (result, vf), "{opcode:04X}");
+        }
+
+        // With VF as the destination the flag overwrites the difference.
+        for (opcode, vf) in [(0x8F25, 1), (0x8F27, 0)] {
+            let mut chip8 = Chip8::new(Quirks::default());
+            chip8.rv[0xF] = 0x30;
+            chip8.rv[2] = 0x20;
+            run(&mut chip8, &[opcode]);
+            assert_eq!(chip8.rv[0xF], vf, "{opcode:04X}");
+        }
+    }
+
+    #[test]
+    fn vf_reset() {
+        for vf_reset in [true, false] {
+            let mut chip8 = Chip8::new(Quirks::default());
+            chip8.quirks.vf_reset = vf_reset;
+            chip8.rv[0] = 0b1100;
+            chip8.rv[1] = 0b1010;
+            chip8.rv[0xF] = 7;
+            run(&mut chip8, &[0x8011]);
+            assert_eq!(chip8.rv[0], 0b1110);
+            assert_eq!(chip8.rv[0xF], if vf_reset { 0 } else { 7 });
+        }
+    }
+
+    #[test]
+    fn shift() {
+        let mut chip8 = Chip8::new(Quirks::default());
+        chip8.rv[0] = 0b0000_0010;
+        chip8.rv[1] = 0b1000_0001;
+        run(&mut chip8, &[0x8016]);
+        assert_eq!((chip8.rv[0], chip8.rv[0xF]), (0b0100_0000, 1));
+        chip8.rv[0] = 0b0000_0010;
+        chip8.pc = 0x200;
+        run(&mut chip8, &[0x801E]);
+        assert_eq!((chip8.rv[0], chip8.rv[0xF]), (0b0000_0010, 1));
+
+        let mut chip8 = Chip8::new(Quirks::default());
+        chip8.quirks.shift = true;
+        chip8.rv[0] = 0b0000_0010;
+        chip8.rv[1] = 0b1000_0001;
+        run(&mut chip8, &[0x8016]);
+        assert_eq!((chip8.rv[0], chip8.rv[0xF]), (0b0000_0001, 0));
+        chip8.pc = 0x200;
+        run(&mut chip8, &[0x801E]);
+        assert_eq!((chip8.rv[0], chip8.rv[0xF]), (0b0000_0010, 0));
+    }
+
+    #[test]
+    fn jump_offset() {
+        let mut chip8 = Chip8::new(Quirks::default());
+        chip8.rv[0] = 0x10;
+        chip8.rv[3] = 0x20;
+        run(&mut chip8, &[0xB300]);
+        assert_eq!(chip8.pc, 0x310);
+
+        let mut chip8 = Chip8::new(Quirks::default());
+        chip8.quirks.jump_vx = true;
+        chip8.rv[0] = 0x10;
+        chip8.rv[3] = 0x20;
+        run(&mut chip8, &[0xB300]);
+        assert_eq!(chip8.pc, 0x320);
+    }
+
+    #[test]
+    fn sprite_clipping() {
+        // Draw the top row of "0", 0b11110000, straddling the right edge at x=62.
+        for wrap_sprites in [false, true] {
+            let mut chip8 = Chip8::new(Quirks::default());
+            chip8.quirks.wrap_sprites = wrap_sprites;
+            chip8.rv[0] = 62;
+            chip8.rv[1] = 31;
+            chip8.ri = FONT_BASE as u16;
+            run(&mut chip8, &[0xD012]);
+            assert_eq!(chip8.display.pixels()[31 * WIDTH + 62..32 * WIDTH], [1, 1]);
+            assert_eq!(
+                chip8.display.pixels()[31 * WIDTH..31 * WIDTH + 2],
+                [wrap_sprites as u8; 2]
+            );
+            // The second row of "0", 0b10010000, is below the bottom edge.
+            assert_eq!(chip8.display.pixels()[62], wrap_sprites as u8);
+            assert_eq!(
+                chip8.display.pixels().iter().filter(|&&p| p == 1).count(),
+                if wrap_sprites { 6 } else { 2 }
+            );
+        }
+    }
+
+    #[test]
+    fn sprite_collision() {
+        let mut chip8 = Chip8::new(Quirks::default());
+        chip8.ri = FONT_BASE as u16;
+        run(&mut chip8, &[0xD005]);
+        assert_eq!(chip8.rv[0xF], 0);
+        // Drawing the same sprite again over the top erases it.
+        chip8.pc = 0x200;
+        chip8.tick_timers();
+        chip8.step().unwrap();
+        assert_eq!(chip8.rv[0xF], 1);
+        assert!(chip8.display.pixels().iter().all(|&p| p == 0));
+    }
+
+    #[test]
+    fn resolution() {
+        let mut chip8 = Chip8::new(Quirks::default());
+        chip8.rv[0] = 100;
+        chip8.rv[1] = 40;
+        chip8.ri = FONT_BASE as u16;
+        run(&mut chip8, &[0x00FF, 0xD011]);
+        assert_eq!((chip8.width(), chip8.height()), (HIRES_WIDTH, HIRES_HEIGHT));
+        assert_eq!(chip8.dimensions(), (HIRES_WIDTH, HIRES_HEIGHT));
+        assert_eq!(chip8.framebuffer().len(), HIRES_WIDTH * HIRES_HEIGHT);
+        assert_eq!(
+            chip8.framebuffer()[40 * HIRES_WIDTH + 100..][..5],
+            [1, 1, 1, 1, 0]
+        );
+
+        // Switching back clears the screen.
+        chip8.load_rom(&[0x00, 0xFE]).unwrap();
+        chip8.pc = 0x200;
+        chip8.tick_timers();
+        assert_eq!(chip8.step(), Ok(StepOutcome::ClearScreen));
+        assert_eq!((chip8.width(), chip8.height()), (WIDTH, HEIGHT));
+        assert!(chip8.display.is_blank());
+    }
+
+    /// Compare the display of `chip8`, after running it headless, against the `expected` bitmap,
+    /// as rendered by [`framebuffer_to_text`]. On a mismatch, the differing pixels are shown with
+    /// `+` where only the actual display is lit and `-` where only the expected one is.
+    fn golden(chip8: &Chip8, expected: &str) {
+        let actual = framebuffer_to_text(chip8.framebuffer(), chip8.width());
+        if actual != expected {
+            let diff: String = actual
+                .chars()
+                .zip(expected.chars().chain(std::iter::repeat(' ')))
+                .map(|pixels| match pixels {
+                    ('#', '.') => '+',
+                    ('.', '#') => '-',
+                    (a, _) => a,
+                })
+                .collect();
+            panic!("display doesn't match the expected bitmap:\n{diff}");
+        }
+    }
+
+    #[test]
+    fn ibm_logo() {
+        let rom = include_bytes!("../IBM_Logo.ch8");
+        let chip8 = run_headless(rom, 100).unwrap();
+        golden(&chip8, include_str!("../tests/golden/ibm_logo.txt"));
+        // After drawing, the program loops forever on a jump to itself.
+        assert_eq!(chip8.pc(), 0x228);
+    }
+
+    /// Run test `n` of Timendus' CHIP-8 test suite for `cycles` instructions, picking it through
+    /// the byte at 0x1FF rather than the suite's menu.
+    fn test_suite(n: u8, cycles: usize) -> Chip8 {
+        let mut chip8 = Chip8::new(Quirks::default());
+        chip8
+            .load_rom(include_bytes!("../tests/roms/chip8-test-suite.ch8"))
+            .unwrap();
+        chip8.poke(0x1FF, n);
+        run_cycles(&mut chip8, cycles).unwrap();
+        chip8
+    }
+
+    #[test]
+    fn corax_opcodes() {
+        // Every opcode gets a tick, with none crossed out.
+        golden(
+            &test_suite(2, 1000),
+            include_str!("../tests/golden/corax_plus.txt"),
+        );
+    }
+
+    #[test]
+    fn flags() {
+        // The carry, borrow and shifted out bits in VF, each with a tick.
+        golden(
+            &test_suite(3, 5000),
+            include_str!("../tests/golden/flags.txt"),
+        );
+    }
+
+    #[test]
+    fn timers() {
+        let mut chip8 = Chip8::new(Quirks::default());
+        chip8.rv[0] = 3;
+        chip8.rv[1] = 1;
+        run(&mut chip8, &[0xF015, 0xF118]);
+        let timers = chip8.timers();
+        std::thread::spawn(move || {
+            for _ in 0..2 {
+                timers.tick();
+            }
+        })
+        .join()
+        .unwrap();
+        assert_eq!((chip8.delay_timer(), chip8.sound_timer()), (1, 0));
+        chip8.pc = 0x200;
+        run(&mut chip8, &[0xF207]);
+        assert_eq!(chip8.rv[2], 1);
+    }
+
+    #[test]
+    fn delay_write_then_read() {
+        let mut chip8 = Chip8::new(Quirks::default());
+        // Set the delay timer to V0 and read it back, then keep reading it into V2.
+        let rom: Vec<u8> = [0xF015, 0xF107, 0xF207, 0x1204]
+            .iter()
+            .flat_map(|i: &u16| i.to_be_bytes())
+            .collect();
+        chip8.load_rom(&rom).unwrap();
+        chip8.rv[0] = 5;
+        // Written and read back within a frame, the timer hasn't moved.
+        chip8.run_frame(2).unwrap();
+        assert_eq!(chip8.rv[1], 5);
+        // Each frame takes exactly one off, however many times it's read in between.
+        for expected in [4, 3, 2, 1, 0, 0] {
+            chip8.run_frame(10).unwrap();
+            assert_eq!(chip8.rv[2], expected);
+        }
+    }
+
+    #[test]
+    fn sound_events() {
+        let mut chip8 = Chip8::new(Quirks::default());
+        let events = Arc::new(Mutex::new(Vec::new()));
+        chip8.on_sound({
+            let events = Arc::clone(&events);
+            move |on| events.lock().unwrap().push(on)
+        });
+        chip8.rv[0] = 2;
+        // Setting the timer again while it's running doesn't start the beep a second time.
+        run(&mut chip8, &[0xF018, 0xF018]);
+        chip8.tick_timers();
+        assert_eq!(*events.lock().unwrap(), [true]);
+        chip8.tick_timers();
+        chip8.tick_timers();
+        assert_eq!(*events.lock().unwrap(), [true, false]);
+        // Setting it to zero stops the beep early.
+        chip8.pc = 0x200;
+        run(&mut chip8, &[0xF018, 0xF118]);
+        assert_eq!(*events.lock().unwrap(), [true, false, true, false]);
+    }
+
+    #[test]
+    fn sound_events_across_threads() {
+        let mut chip8 = Chip8::new(Quirks::default());
+        let events = Arc::new(Mutex::new(Vec::new()));
+        chip8.on_sound({
+            let events = Arc::clone(&events);
+            move |on| events.lock().unwrap().push(on)
+        });
+        // Ticking on one thread while the program sets the timer on another, the hook is told
+        // about every change in order, so it always alternates and ends up agreeing with the timer.
+        let timers = chip8.timers();
+        let ticker = std::thread::spawn(move || {
+            for _ in 0..10_000 {
+                timers.tick();
+            }
+        });
+        for i in 0..10_000 {
+            chip8.timers.set_sound(i as u8 % 3);
+        }
+        ticker.join().unwrap();
+        let events = events.lock().unwrap();
+        assert!(events.windows(2).all(|pair| pair[0] != pair[1]));
+        assert_eq!(
+            events.last().copied().unwrap_or(false),
+            chip8.sound_timer() > 0
+        );
+    }
+
+    #[test]
+    fn frames() {
+        // Wait a frame on the delay timer, then draw once a key is pressed.
+        let rom = [
+            0x60, 0x01, 0xF0, 0x15, 0xF1, 0x07, 0x31, 0x00, 0x12, 0x04, 0xF2, 0x0A, 0xD0, 0x05,
+            0x12, 0x0E,
+        ];
+        let mut chip8 = Chip8::new(Quirks::default());
+        chip8.load_rom(&rom).unwrap();
+        assert_eq!(chip8.run_frame(20), Ok(false));
+        assert_eq!(chip8.run_frame(20), Ok(false));
+        assert_eq!(chip8.pc, 0x20A);
+        // `FX0A` waits for the key to be released.
+        chip8.set_key(0xA, true);
+        assert_eq!(chip8.run_frame(20), Ok(false));
+        chip8.set_key(0xA, false);
+        assert_eq!(chip8.run_frame(20), Ok(true));
+        assert_eq!(chip8.pc, 0x20E);
+    }
+
+    #[test]
+    fn rpl_flags() {
+        let mut chip8 = Chip8::new(Quirks::default());
+        chip8.rv = [0xAA; 16];
+        chip8.rv[..3].copy_from_slice(&[1, 2, 3]);
+        run(&mut chip8, &[0xF275]);
+        chip8.rv = [0; 16];
+        chip8.pc = 0x200;
+        // Only 8 flags can be restored.
+        run(&mut chip8, &[0xFF85]);
+        assert_eq!(chip8.rv[..9], [1, 2, 3, 0, 0, 0, 0, 0, 0]);
+
+        chip8.rv = [0xAA; 16];
+        chip8.pc = 0x200;
+        run(&mut chip8, &[0xFF75, 0xF185]);
+        assert_eq!(chip8.rpl, [0xAA; 8]);
+    }
+
+    #[test]
+    fn big_font_sprite() {
+        let mut chip8 = Chip8::new(Quirks::default());
+        chip8.rv[0] = 0x18;
+        run(&mut chip8, &[0xF030]);
+        assert_eq!(chip8.ri as usize, BIG_FONT_BASE + 8 * 10);
+        assert_eq!(
+            chip8.memory[chip8.ri as usize..][..10],
+            [0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF]
+        );
+    }
+
+    #[test]
+    fn self_jump() {
+        let mut chip8 = Chip8::new(Quirks::default());
+        chip8.load_rom(&[0x12, 0x02, 0x12, 0x02]).unwrap();
+        assert_eq!(chip8.step(), Ok(StepOutcome::Continue));
+        for _ in 0..2 {
+            assert_eq!(chip8.step(), Ok(StepOutcome::Halt));
+            assert_eq!(chip8.pc, 0x202);
+        }
+    }
+
+    #[test]
+    fn exit() {
+        let mut chip8 = Chip8::new(Quirks::default());
+        chip8.load_rom(&[0x00, 0xFD]).unwrap();
+        for _ in 0..2 {
+            assert_eq!(chip8.step(), Ok(StepOutcome::Halt));
+            assert_eq!(chip8.pc, 0x200);
+        }
+    }
+
+    #[test]
+    fn scroll() {
+        for (hires, width) in [(false, WIDTH), (true, HIRES_WIDTH)] {
+            let mut chip8 = Chip8::new(Quirks::default());
+            chip8
+                .display
+                .resize(width, if hires { HIRES_HEIGHT } else { HEIGHT });
+            chip8.display.set(10, 2, 1);
+            run(&mut chip8, &[0x00C3]);
+            assert_eq!(
+                chip8.display.pixels().iter().position(|&p| p == 1),
+                Some(5 * width + 10)
+            );
+            chip8.pc = 0x200;
+            run(&mut chip8, &[0x00FB]);
+            assert_eq!(
+                chip8.display.pixels().iter().position(|&p| p == 1),
+                Some(5 * width + 14)
+            );
+            chip8.pc = 0x200;
+            run(&mut chip8, &[0x00FC, 0x00FC]);
+            assert_eq!(
+                chip8.display.pixels().iter().position(|&p| p == 1),
+                Some(5 * width + 6)
+            );
+            // Pixels scrolled off the edge are lost.
+            chip8.pc = 0x200;
+            run(&mut chip8, &[0x00FC, 0x00FC, 0x00FB, 0x00FB]);
+            assert!(chip8.display.pixels().iter().all(|&p| p == 0));
+        }
+    }
+
+    #[test]
+    fn planes() {
+        let mut chip8 = Chip8::new(Quirks::xochip());
+        // One row for the first plane, then one for the second.
+        chip8.memory[0x300..0x302].copy_from_slice(&[0b1100_0000, 0b1010_0000]);
+        chip8.ri = 0x300;
+        run(&mut chip8, &[0xF301, 0xD001]);
+        assert_eq!(chip8.display.pixels()[..4], [3, 1, 2, 0]);
+        assert_eq!(chip8.rv[0xF], 0);
+
+        // Only the second plane, which collides and takes its sprite from I.
+        chip8.pc = 0x200;
+        run(&mut chip8, &[0xF201, 0xD001]);
+        assert_eq!(chip8.display.pixels()[..4], [1, 3, 2, 0]);
+        assert_eq!(chip8.rv[0xF], 1);
+
+        // Scrolling moves only the selected planes.
+        chip8.pc = 0x200;
+        run(&mut chip8, &[0xF101, 0x00FB]);
+        assert_eq!(chip8.display.pixels()[..6], [0, 2, 2, 0, 1, 1]);
+
+        // With no planes selected nothing is drawn.
+        chip8.pc = 0x200;
+        run(&mut chip8, &[0xF001, 0xD001]);
+        assert_eq!(chip8.display.pixels()[..6], [0, 2, 2, 0, 1, 1]);
+
+        // Clearing the second plane leaves the first alone, so the display isn't blank.
+        chip8.pc = 0x200;
+        chip8.load_rom(&[0xF2, 0x01, 0x00, 0xE0]).unwrap();
+        chip8.step().unwrap();
+        assert_eq!(chip8.step(), Ok(StepOutcome::Draw));
+        assert_eq!(chip8.display.pixels()[..6], [0, 0, 0, 0, 1, 1]);
+        chip8.pc = 0x200;
+        chip8.load_rom(&[0xF3, 0x01, 0x00, 0xE0]).unwrap();
+        chip8.step().unwrap();
+        assert_eq!(chip8.step(), Ok(StepOutcome::ClearScreen));
+        assert!(chip8.display.pixels().iter().all(|&p| p == 0));
+    }
+
+    #[test]
+    fn long_index() {
+        // `run` can't be used since these instructions are two words long.
+        let mut chip8 = Chip8::new(Quirks::xochip());
+        assert_eq!(chip8.memory.len(), 0x10000);
+        chip8.rv[0] = 0xAB;
+        chip8
+            .load_rom(&[0xF0, 0x00, 0xFF, 0xF0, 0xF0, 0x55])
+            .unwrap();
+        chip8.step().unwrap();
+        assert_eq!(chip8.pc, 0x204);
+        assert_eq!(chip8.ri, 0xFFF0);
+        // All 16 bits of I are used to access memory.
+        chip8.step().unwrap();
+        assert_eq!(chip8.memory[0xFFF0], 0xAB);
+        assert_eq!(chip8.ri, 0xFFF1);
+
+        chip8.pc = 0x200;
+        chip8
+            .load_rom(&[0xF0, 0x00, 0xFF, 0xFF, 0xF1, 0x55])
+            .unwrap();
+        chip8.step().unwrap();
+        // Stores past the end of memory wrap around to the start.
+        chip8.rv[1] = 0xCD;
+        assert_eq!(chip8.step(), Ok(StepOutcome::Continue));
+        assert_eq!([chip8.memory[0xFFFF], chip8.memory[0]], [0xAB, 0xCD]);
+        assert_eq!(
+            chip8.take_warning(),
+            Some(Warning::MemoryWrapped {
+                pc: 0x204,
+                addr: 0xFFFF
+            })
+        );
+
+        // Skips step over both halves of the instruction.
+        chip8.pc = 0x200;
+        chip8
+            .load_rom(&[0x30, 0xAB, 0xF0, 0x00, 0x12, 0x34])
+            .unwrap();
+        chip8.step().unwrap();
+        assert_eq!(chip8.pc, 0x206);
+    }
+
+    #[test]
+    fn audio_pattern() {
+        let mut chip8 = Chip8::new(Quirks::xochip());
+        assert_eq!(chip8.audio_pattern(), None);
+        chip8.memory[0x300..0x310].copy_from_slice(&[0xF0; 16]);
+        chip8.ri = 0x300;
+        chip8.rv[1] = 112;
+        run(&mut chip8, &[0xF002, 0xF13A]);
+        let pattern = chip8.audio_pattern().unwrap();
+        assert_eq!(pattern.bits, [0xF0; 16]);
+        assert_eq!(pattern.pitch, 112);
+        assert_eq!(pattern.rate(), 8000.0);
+        assert!(pattern.sample(0) && pattern.sample(3) && pattern.sample(128));
+        assert!(!pattern.sample(4) && !pattern.sample(7));
+
+        chip8.reset();
+        assert_eq!(chip8.audio_pattern(), None);
+        assert_eq!(chip8.pitch, AudioPattern::DEFAULT_PITCH);
+    }
+
+    #[test]
+    fn displayed_digits() {
+        let mut chip8 = Chip8::new(Quirks::default());
+        // Draw 3 at (0, 0), A at (10, 2) and 8 immediately right of A, with no gap.
+        chip8.rv[..6].copy_from_slice(&[0x3, 0xA, 0x8, 0, 10, 2]);
+        run(
+            &mut chip8,
+            &[0xF029, 0xD335, 0xF129, 0xD455, 0x7404, 0xF229, 0xD455],
+        );
+        assert_eq!(chip8.displayed_digits(), [(0, 0, 3)]);
+
+        // Move the 8 one pixel right to leave a gap.
+        chip8.pc = 0x200;
+        run(&mut chip8, &[0xD455, 0x7401, 0xD455]);
+        assert_eq!(
+            chip8.displayed_digits(),
+            [(0, 0, 3), (10, 2, 0xA), (15, 2, 8)]
+        );
+    }
+
+    #[test]
+    fn big_sprite() {
+        let mut chip8 = Chip8::new(Quirks::default());
+        chip8.memory[0x300..0x320].copy_from_slice(&[0xFF, 0x01].repeat(16));
+        chip8.rv[0] = 120;
+        chip8.rv[1] = 50;
+        chip8.ri = 0x300;
+        run(&mut chip8, &[0x00FF, 0xD010]);
+        assert_eq!(chip8.rv[0xF], 0);
+        for y in 50..64 {
+            let row = &chip8.display.pixels()[y * HIRES_WIDTH..][..HIRES_WIDTH];
+            assert_eq!(row[120..], [1; 8], "row {y}");
+            assert!(row[..120].iter().all(|&p| p == 0), "row {y}");
+        }
+        // Drawing again collides.
+        chip8.pc = 0x202;
+        chip8.tick_timers();
+        chip8.step().unwrap();
+        assert_eq!(chip8.rv[0xF], 1);
+        assert_eq!(chip8.take_warning(), None);
+    }
+
+    #[test]
+    fn display_wait() {
+        // Draw, then set V0.
+        let rom = [0xD0, 0x01, 0x60, 0x01];
+        let mut chip8 = Chip8::new(Quirks::chip8());
+        chip8.load_rom(&rom).unwrap();
+        // A tick from before the draw doesn't count.
+        chip8.tick_timers();
+        assert_eq!(chip8.step(), Ok(StepOutcome::Draw));
+        assert_eq!(chip8.step(), Ok(StepOutcome::WaitingForFrame));
+        assert_eq!(chip8.pc, 0x202);
+        chip8.timers().tick();
+        assert_eq!(chip8.step(), Ok(StepOutcome::Continue));
+        assert_eq!(chip8.rv[0], 1);
+
+        // A frame's instructions end with the draw.
+        let mut chip8 = Chip8::new(Quirks::chip8());
+        chip8.load_rom(&rom).unwrap();
+        assert_eq!(chip8.run_frame(2), Ok(true));
+        assert_eq!(chip8.pc, 0x202);
+
+        let mut chip8 = Chip8::new(Quirks::superchip());
+        chip8.load_rom(&rom).unwrap();
+        assert_eq!(chip8.step(), Ok(StepOutcome::Draw));
+        assert_eq!(chip8.step(), Ok(StepOutcome::Continue));
+    }
+
+    #[test]
+    fn sprite_memory_wrap() {
+        let mut chip8 = Chip8::new(Quirks::default());
+        chip8.memory[0xFFF] = 0x80;
+        chip8.memory[0x000] = 0x40;
+        chip8.ri = 0xFFF;
+        run(&mut chip8, &[0xD002]);
+        assert_eq!(chip8.display.pixels()[..2], [1, 0]);
+        assert_eq!(chip8.display.pixels()[WIDTH..WIDTH + 2], [0, 1]);
+        assert_eq!(
+            chip8.take_warning(),
+            Some(Warning::SpriteWrapped {
+                pc: 0x200,
+                addr: 0xFFF
+            })
+        );
+        assert_eq!(chip8.take_warning(), None);
+    }
+
+    #[test]
+    fn code_overwritten() {
+        // Store V0 over the instruction which was executed just before.
+        let mut chip8 = Chip8::new(Quirks::default());
+        chip8.ri = 0x200;
+        run(&mut chip8, &[0x6000, 0xF055]);
+        assert_eq!(
+            chip8.take_warning(),
+            Some(Warning::CodeOverwritten {
+                pc: 0x202,
+                addr: 0x200
+            })
+        );
+        // Data after the program is fine.
+        chip8.pc = 0x200;
+        chip8.ri = 0x300;
+        run(&mut chip8, &[0xF033, 0xF155]);
+        assert_eq!(chip8.take_warning(), None);
+    }
+
+    #[test]
+    fn store_load_registers() {
+        for memory_increment in [true, false] {
+            let mut chip8 = Chip8::new(Quirks::default());
+            chip8.quirks.memory_increment = memory_increment;
+            chip8.rv[..4].copy_from_slice(&[1, 2, 3, 4]);
+            chip8.ri = 0x300;
+            run(&mut chip8, &[0xF355]);
+            assert_eq!(chip8.memory[0x300..0x305], [1, 2, 3, 4, 0]);
+            assert_eq!(chip8.ri, if memory_increment { 0x304 } else { 0x300 });
+
+            chip8.rv = [0; 16];
+            chip8.ri = 0x300;
+            chip8.pc = 0x200;
+            run(&mut chip8, &[0xF265]);
+            assert_eq!(chip8.rv[..4], [1, 2, 3, 0]);
+            assert_eq!(chip8.ri, if memory_increment { 0x303 } else { 0x300 });
+        }
+    }
+
+    #[test]
+    fn reset() {
+        // Draw a digit, then overwrite the start of the font and of the ROM itself.
+        let rom = [
+            0x61, 0x0A, 0xF1, 0x29, 0xD0, 0x05, 0xA0, 0x4F, 0xF1, 0x55, 0xA2, 0x00, 0xF1, 0x55,
+        ];
+        let mut chip8 = Chip8::new(Quirks::default());
+        chip8.load_rom(&rom).unwrap();
+        for _ in 0..7 {
+            chip8.tick_timers();
+            chip8.step().unwrap();
+        }
+        assert_eq!(chip8.memory[0x200..0x202], [0x00, 0x0A]);
+
+        chip8.reset();
+        let mut fresh = Chip8::new(Quirks::default());
+        fresh.load_rom(&rom).unwrap();
+        assert_eq!(chip8.memory, fresh.memory);
+        assert_eq!(chip8.display, fresh.display);
+        assert_eq!((chip8.pc, chip8.ri, chip8.rv), (0x200, 0, [0; 16]));
+    }
+
+    #[test]
+    fn load_address() {
+        // An ETI 660 program, loaded and started at 0x600 in 8KB of memory.
+        let mut chip8 = Chip8::new(Quirks {
+            memory_size: 0x2000,
+            load_address: 0x600,
+            ..Quirks::default()
+        });
+        chip8.load_rom(&[0x16, 0x00]).unwrap();
+        assert_eq!(chip8.pc, 0x600);
+        assert_eq!(chip8.memory.len(), 0x2000);
+        assert_eq!(chip8.memory[0x600..0x602], [0x16, 0x00]);
+        assert_eq!(chip8.memory[FONT_BASE], 0xF0);
+        chip8.step().unwrap();
+        assert_eq!(chip8.pc, 0x600);
+        chip8.reset();
+        assert_eq!(chip8.pc, 0x600);
+    }
+
+    #[test]
+    fn byte_swap() {
+        let mut chip8 = Chip8::new(Quirks {
+            byte_swap: true,
+            ..Quirks::default()
+        });
+        chip8.load_rom(&[0x05, 0x60, 0x01]).unwrap();
+        assert_eq!(chip8.memory[0x200..0x203], [0x60, 0x05, 0x01]);
+        chip8.step().unwrap();
+        assert_eq!(chip8.rv[0], 5);
+        // The ROM is swapped again when it's reloaded.
+        chip8.reset();
+        assert_eq!(chip8.memory[0x200..0x203], [0x60, 0x05, 0x01]);
+    }
+
+    #[test]
+    fn poke() {
+        let mut chip8 = Chip8::new(Quirks::default());
+        chip8.load_rom(&[0x60, 0x01]).unwrap();
+        chip8.poke(0x201, 0x05);
+        chip8.poke(0x1000, 0xFF);
+        chip8.step().unwrap();
+        assert_eq!(chip8.rv[0], 5);
+    }
+
+    #[test]
+    fn rom_too_large() {
+        let mut chip8 = Chip8::new(Quirks::default());
+        let max = 0x1000 - 0x200;
+        assert_eq!(
+            chip8.load_rom(&vec![0xFF; max + 1]),
+            Err(Chip8Error::RomTooLarge { len: max + 1, max })
+        );
+        assert!(chip8.memory[0x200..].iter().all(|&b| b == 0));
+        chip8.load_rom(&vec![0xFF; max]).unwrap();
+        assert!(chip8.memory[0x200..].iter().all(|&b| b == 0xFF));
+    }
+}
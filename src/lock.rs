@@ -0,0 +1,65 @@
+//! A lock for the sound hook, which the timers call from whichever thread ticks them. With `std`
+//! it's a `Mutex`. Without it there's nothing to block on, so it spins, which is fine for a hook
+//! that's only held while it's being replaced or called.
+
+use core::fmt;
+#[cfg(not(feature = "std"))]
+use core::{
+    cell::UnsafeCell,
+    hint,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+#[cfg(feature = "std")]
+#[derive(Default)]
+pub struct Lock<T>(std::sync::Mutex<T>);
+
+#[cfg(feature = "std")]
+impl<T> Lock<T> {
+    /// Run `f` with the value locked.
+    pub fn with<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        f(&mut self.0.lock().expect("no panics holding lock"))
+    }
+}
+
+#[cfg(not(feature = "std"))]
+#[derive(Default)]
+pub struct Lock<T> {
+    locked: AtomicBool,
+    value: UnsafeCell<T>,
+}
+
+// SAFETY: the value is only reached through `with`, which holds the lock for as long as it's
+// borrowed.
+#[cfg(not(feature = "std"))]
+unsafe impl<T: Send> Sync for Lock<T> {}
+
+#[cfg(not(feature = "std"))]
+impl<T> Lock<T> {
+    /// Run `f` with the value locked.
+    pub fn with<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        while self
+            .locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            hint::spin_loop();
+        }
+        /// Unlocks when dropped, so that a panic in `f` doesn't leave it locked forever.
+        struct Unlock<'a>(&'a AtomicBool);
+        impl Drop for Unlock<'_> {
+            fn drop(&mut self) {
+                self.0.store(false, Ordering::Release);
+            }
+        }
+        let _unlock = Unlock(&self.locked);
+        // SAFETY: we hold the lock, so nothing else is borrowing the value.
+        f(unsafe { &mut *self.value.get() })
+    }
+}
+
+impl<T> fmt::Debug for Lock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Lock { .. }")
+    }
+}
@@ -1,296 +1,973 @@
-use std::{sync::mpsc, thread, time::Duration};
-
-const WIDTH: usize = 64;
-const HEIGHT: usize = 32;
-
-#[derive(Debug)]
-struct Chip8 {
-    memory: Box<[u8; 4096]>,
-    display: Box<[u8; WIDTH * HEIGHT]>,
-    pc: u16,
-    ri: u16,
-    delay_timer: u8,
-    sound_timer: u8,
-    rv: [u8; 16],
-    stack: Vec<u16>,
+use std::{
+    collections::BTreeMap,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use args::Args;
+use chip8::{Chip8, Chip8Error, Display, Quirks, StepOutcome};
+use input::Input;
+#[cfg(feature = "meta")]
+use meta::RomMeta;
+
+mod args;
+mod audio;
+mod bench;
+mod debug;
+mod input;
+#[cfg(feature = "meta")]
+mod meta;
+mod profile;
+#[cfg(feature = "record")]
+mod record;
+mod render;
+mod replay;
+mod screenshot;
+mod trace;
+#[cfg(feature = "window")]
+mod window;
+
+/// Something for the event loop to handle.
+enum Event {
+    /// A 60Hz tick of the timers.
+    Tick,
+    /// Time to execute the next instruction.
+    Clock,
+    /// With frame timing, the end of a frame's instructions and time to draw.
+    FrameEnd,
+    Input(Input),
 }
 
-impl Chip8 {
-    fn new() -> Self {
-        const FONT_DATA: [u8; 80] = [
-            0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
-            0x20, 0x60, 0x20, 0x20, 0x70, // 1
-            0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
-            0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
-            0x90, 0x90, 0xF0, 0x10, 0x10, // 4
-            0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
-            0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
-            0xF0, 0x10, 0x20, 0x40, 0x40, // 7
-            0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
-            0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
-            0xF0, 0x90, 0xF0, 0x90, 0x90, // A
-            0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
-            0xF0, 0x80, 0x80, 0x80, 0xF0, // C
-            0xE0, 0x90, 0x90, 0x90, 0xE0, // D
-            0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
-            0xF0, 0x80, 0xF0, 0x80, 0x80, // F
-        ];
-        let memory: Box<[u8; 4096]> = [0; 0x4f]
-            .into_iter()
-            .chain(FONT_DATA)
-            .chain([0; 4096 - 0x4f - FONT_DATA.len()])
-            .collect::<Vec<_>>()
-            .into_boxed_slice()
-            .try_into()
-            .unwrap_or_else(|e: Box<[_]>| panic!("expected 4096 bytes but got {}", e.len()));
-        Self {
-            memory,
-            display: Box::new([0; WIDTH * HEIGHT]),
-            pc: 0x200,
-            ri: 0x0,
-            delay_timer: 0,
-            sound_timer: 0,
-            rv: [0; 16],
-            stack: Vec::new(),
-        }
-    }
+/// How often the register overlay is refreshed while it's shown, once a 60Hz frame.
+const OVERLAY_REFRESH: Duration = Duration::from_micros(16_667);
 
-    fn load_rom(&mut self, rom: &[u8]) {
-        for (i, b) in (0x200..).zip(rom.iter()) {
-            self.memory[i] = *b;
-        }
+/// The latest frame, shared with the draw thread or the window.
+struct Frame {
+    display: Display,
+    /// The registers to show below the display, or empty with the overlay off.
+    overlay: String,
+}
+
+impl From<Input> for Event {
+    fn from(input: Input) -> Self {
+        Self::Input(input)
     }
 }
 
-// To avoid bringing in rand, simple PRNG implementation using LSFR.
-// <https://en.wikipedia.org/wiki/Linear-feedback_shift_register>
-struct Lfsr(u8);
-impl Lfsr {
-    // 10110100
-    fn next(&mut self) -> u8 {
-        let bit = (self.0 >> 7) ^ (self.0 >> 5) ^ (self.0 >> 4) ^ (self.0 >> 2);
-        self.0 = (bit << 7) | (self.0 >> 1);
-        self.0
+/// The fastest that `+` speeds the clock up to, far more than the emulator can keep up with.
+/// Faster speeds given with `--hz` are left alone.
+const MAX_SPEED: u32 = 10_000_000;
+
+/// The clock speed after pressing `+` at `speed`, 10% faster.
+fn faster(speed: u32) -> u32 {
+    speed
+        .saturating_add((speed / 10).max(1))
+        .min(MAX_SPEED.max(speed))
+}
+
+/// The clock speed after pressing `-` at `speed`, 10% slower but never stopped.
+fn slower(speed: u32) -> u32 {
+    speed - (speed / 10).min(speed - 1)
+}
+
+fn parse_args(args: impl IntoIterator<Item = String>) -> Args {
+    match Args::parse(args) {
+        Ok(args) => args,
+        Err(args::Error::Help) => {
+            println!("{}", args::USAGE);
+            process::exit(0);
+        }
+        Err(args::Error::Invalid(e)) => {
+            eprintln!("error: {e}\n\n{}", args::USAGE);
+            process::exit(2);
+        }
     }
 }
 
 fn main() {
-    let mut chip8 = Chip8::new();
-    chip8.load_rom(&std::fs::read("test_opcode.ch8").unwrap());
+    let mut args = parse_args(std::env::args().skip(1));
+    // Settings from the ROM's metadata are defaults, which options given on the command line
+    // override.
+    #[cfg(feature = "meta")]
+    let meta = if args.rom == Path::new("-") {
+        None
+    } else {
+        RomMeta::for_rom(&args.rom).unwrap_or_else(|e| {
+            eprintln!("warning: ignoring the ROM's metadata: {e}");
+            None
+        })
+    };
+    #[cfg(feature = "meta")]
+    if let Some(meta) = &meta {
+        meta.apply(&mut args);
+    }
+    // A ROM path of `-` means to read the ROM from stdin.
+    let from_stdin = args.rom == Path::new("-");
+    let rom_name = if from_stdin {
+        "stdin".to_owned()
+    } else {
+        format!("`{}`", args.rom.display())
+    };
+    let rom = if from_stdin {
+        let mut rom = Vec::new();
+        io::stdin().lock().read_to_end(&mut rom).map(|_| rom)
+    } else {
+        std::fs::read(&args.rom)
+    };
+    let rom = match rom {
+        Ok(rom) => rom,
+        Err(e) => {
+            eprintln!("error: failed to read {rom_name}: {e}");
+            process::exit(1);
+        }
+    };
+    // With `--detect-quirks`, known games get the quirks they need.
+    let detected = args.detect_quirks.then(|| match Quirks::detect(&rom) {
+        Some((title, preset)) => {
+            args.use_preset(preset);
+            format!(
+                "detected {title}, using the `{}` quirks{}",
+                preset.preset_name().expect("detected quirks are a preset"),
+                if args.quirk_overrides.is_empty() {
+                    ""
+                } else {
+                    " with overrides"
+                }
+            )
+        }
+        None => match args.quirks.preset_name() {
+            Some(name) => format!("{rom_name} isn't a known ROM, using the `{name}` quirks"),
+            None => format!("{rom_name} isn't a known ROM, using the quirks given"),
+        },
+    });
+    let quirks = args.quirks;
+    let mut chip8 = Chip8::new(quirks);
+    if let Err(e) = chip8.load_rom(&rom) {
+        eprintln!("error: failed to load {rom_name}: {e}");
+        process::exit(1);
+    }
+    // Pokes patch the ROM, so they're applied again whenever it's restarted.
+    let apply_pokes = |chip8: &mut Chip8| {
+        for &(addr, value) in &args.pokes {
+            chip8.poke(addr, value);
+        }
+    };
+    apply_pokes(&mut chip8);
 
-    const CLEAR: &str = "\x1B[2J\x1B[1;1H";
-    print!("{CLEAR}");
+    // The program as it was loaded, after any byte swapping and pokes.
+    let program = &chip8.memory()[quirks.load_address..][..rom.len()];
+    if args.disassemble {
+        for (addr, opcode, mnemonic) in chip8::disasm::disassemble(program, quirks.load_address) {
+            println!("{addr:03X}  {opcode:04X}  {mnemonic}");
+        }
+        return;
+    }
 
-    // The delay clock pulses at 60Hz to signal when to decrement the `delay_timer` and `sound_timer`.
-    let (delay_clock_tx, delay_clock_rx) = mpsc::channel();
-    let _delay_clock = thread::spawn(move || {
-        let delay = Duration::from_secs_f64(1.0 / 60.0);
-        loop {
-            thread::sleep(delay);
-            delay_clock_tx.send(()).expect("main thread owns receiver");
+    if args.verify {
+        let unsupported = chip8::disasm::unsupported(program, quirks.load_address);
+        for (addr, opcode) in &unsupported {
+            println!("{addr:03X}  {opcode:04X}");
         }
-    });
+        println!(
+            "{} potentially unsupported opcodes found",
+            unsupported.len()
+        );
+        process::exit(if unsupported.is_empty() { 0 } else { 1 });
+    }
 
-    // The clock pulses to ensure 700 instructions are FDE'd per second.
-    let (clock_tx, clock_rx) = mpsc::channel();
-    let _clock = thread::spawn(move || {
-        let delay = Duration::from_secs_f64(1.0 / 700.0);
-        loop {
-            thread::sleep(delay);
-            clock_tx.send(()).expect("main thread owns receiver");
+    if let Some(seed) = args.seed {
+        if seed == 0 {
+            eprintln!(
+                "warning: the random number generator can't be seeded with 0, using {} instead",
+                chip8::Lfsr::DEFAULT_SEED
+            );
         }
-    });
+        chip8.seed_prng(seed);
+    }
 
-    let (draw_tx, draw_rx) = mpsc::channel::<Box<[u8; WIDTH * HEIGHT]>>();
-    let _draw = thread::spawn(move || {
-        use std::io::Write;
-        const RESET_CURSOR: &str = "\x1B[1;1H";
-        // TODO: Optimisation: if we were too slow and there are multiple frames in the queue, we
-        // only need to render the most recent one and can drop the rest.
-        while let Ok(buf) = draw_rx.recv() {
-            print!("{RESET_CURSOR}");
-            for y in (0..HEIGHT).step_by(2) {
-                for x in 0..WIDTH {
-                    print!(
-                        "{}",
-                        match (buf[y * WIDTH + x], buf[(y + 1) * WIDTH + x]) {
-                            (0, 0) => " ",
-                            (1, 0) => "\u{2580}",
-                            (0, 1) => "\u{2584}",
-                            (1, 1) => "\u{2588}",
-                            _ => unreachable!(),
-                        }
-                    );
-                }
-                println!();
+    // Without the clock or any terminal I/O, this measures only the interpreter itself.
+    if args.bench {
+        if let Some(detected) = &detected {
+            eprintln!("{detected}");
+        }
+        match bench::run(&mut chip8, bench::BENCH_TIME) {
+            Ok((steps, elapsed)) => eprintln!(
+                "{steps} instructions in {:.2}s: {:.0} instructions per second",
+                elapsed.as_secs_f64(),
+                steps as f64 / elapsed.as_secs_f64()
+            ),
+            Err(e) => {
+                eprintln!("error: {e}");
+                process::exit(1);
             }
-            drop(std::io::stdout().flush());
         }
+        return;
+    }
+
+    // A replay gets the seed it was recorded with.
+    let mut replay = args.replay.as_ref().map(|path| {
+        replay::Replay::open(path).unwrap_or_else(|e| {
+            eprintln!("error: failed to read `{}`: {e}", path.display());
+            process::exit(1);
+        })
+    });
+    if let Some(seed) = replay.as_ref().and_then(|replay| replay.seed) {
+        chip8.seed_prng(seed);
+    }
+    let mut input_recorder = args.record_input.as_ref().map(|path| {
+        let seed = args
+            .seed
+            .filter(|&seed| seed != 0)
+            .unwrap_or(chip8::Lfsr::DEFAULT_SEED);
+        replay::InputRecorder::create(path, seed).unwrap_or_else(|e| {
+            eprintln!("error: failed to create `{}`: {e}", path.display());
+            process::exit(1);
+        })
     });
 
-    let mut prng = Lfsr(0xFF);
+    let mut tracer = args.trace.as_ref().map(|path| {
+        trace::Tracer::append(path).unwrap_or_else(|e| {
+            eprintln!("error: failed to open `{}`: {e}", path.display());
+            process::exit(1);
+        })
+    });
 
-    // Event loop
-    loop {
-        if delay_clock_rx.try_recv().is_ok() {
-            chip8.delay_timer = chip8.delay_timer.saturating_sub(1);
-            chip8.sound_timer = chip8.sound_timer.saturating_sub(1);
-        }
+    let mut profile = args.profile.then(profile::Profile::new);
 
-        if clock_rx.try_recv().is_err() {
-            continue;
+    // Recordings are drawn at the same size as screenshots, so each pixel of the high resolution
+    // display is half a low resolution one.
+    #[cfg(feature = "record")]
+    let recorder = args.record.as_ref().map(|path| {
+        record::Recorder::create(path, args.scale.div_ceil(2)).unwrap_or_else(|e| {
+            eprintln!("error: failed to create `{}`: {e}", path.display());
+            process::exit(1);
+        })
+    });
+    #[cfg(not(feature = "record"))]
+    if args.record.is_some() {
+        eprintln!("error: `--record` needs chip8 to be built with `--features record`");
+        process::exit(1);
+    }
+    #[cfg(not(feature = "window"))]
+    if args.window {
+        eprintln!("error: `--window` needs chip8 to be built with `--features window`");
+        process::exit(1);
+    }
+    // With `--window` the terminal only gets status messages, printed to stderr as without the
+    // display.
+    let terminal_display = !args.no_display && !args.window;
+
+    // The cursor is shown again when `hidden_cursor` is dropped, which also happens when unwinding
+    // from a panic.
+    const CLEAR: &str = "\x1B[2J\x1B[1;1H";
+    let hidden_cursor = terminal_display.then(|| {
+        print!("{CLEAR}");
+        render::HiddenCursor::hide()
+    });
+
+    // The event loop sleeps until there's an instruction to execute or input to handle, both of
+    // which arrive through `event_tx`.
+    let (event_tx, event_rx) = mpsc::channel();
+
+    // Whether the machine is running rather than paused in the debugger. Time stands still while
+    // paused, so the clocks stop.
+    let running = Arc::new(AtomicBool::new(!args.debug));
+
+    // Set when the event loop has finished, telling the clock threads to stop.
+    let shutdown = Arc::new(AtomicBool::new(false));
+
+    // How fast instructions are executed: `hz` per second, or with frame timing
+    // `cycles_per_frame` per 60Hz frame. It can be changed at runtime from the keyboard, and is
+    // multiplied by `TURBO` while the turbo key is held.
+    const TURBO: u32 = 4;
+    let speed = Arc::new(AtomicU32::new(args.cycles_per_frame.unwrap_or(args.hz)));
+    let turbo = Arc::new(AtomicBool::new(false));
+    let current_speed = |speed: &AtomicU32, turbo: &AtomicBool| {
+        let speed = speed.load(Ordering::Relaxed);
+        if turbo.load(Ordering::Relaxed) {
+            speed.saturating_mul(TURBO)
+        } else {
+            speed
         }
+    };
 
-        // Fetch
-        let current_instruction = ((chip8.memory[chip8.pc as usize] as u16) << 8)
-            + chip8.memory[chip8.pc as usize + 1] as u16;
-        chip8.pc += 2;
-
-        /// Index by nibble i from some the current instruction.
-        /// e.g. i=0123
-        ///      0xFFFF
-        macro_rules! nibble {
-            ($i:expr) => {
-                current_instruction as usize >> (4 * (3 - $i)) & 0xf
-            };
-        }
-        macro_rules! rv {
-            (X) => {
-                chip8.rv[nibble!(1)]
-            };
-            (Y) => {
-                chip8.rv[nibble!(2)]
-            };
-        }
-
-        // Decode + Execute
-        match current_instruction >> 12 & 0xf {
-            0x0 => match current_instruction {
-                // Clear screen.
-                0x00E0 => {
-                    *chip8.display = [0; WIDTH * HEIGHT];
-                    draw_tx
-                        .send(chip8.display.clone())
-                        .expect("rx thread loops forever");
+    // The timers are ticked at 60Hz by their own thread, so that they keep time however slowly
+    // instructions are executed. The ticks go through the event loop like the clock, so that they
+    // land between instructions: `FX07` straight after `FX15` reads back what was written unless a
+    // tick came in between. With frame timing each tick also starts a frame, clocking that frame's
+    // instructions and then marking its end so that the display is drawn once per frame.
+    let timers_tx = event_tx.clone();
+    let timers_running = Arc::clone(&running);
+    let frame_timing = args.cycles_per_frame.is_some();
+    let frame_tx = event_tx.clone();
+    let frame_speed = Arc::clone(&speed);
+    let frame_turbo = Arc::clone(&turbo);
+    let timers_shutdown = Arc::clone(&shutdown);
+    let timers_thread = thread::spawn(move || {
+        let start = Instant::now();
+        for n in 1u64.. {
+            if timers_shutdown.load(Ordering::Relaxed) {
+                return;
+            }
+            // Tick n is due n/60s after the start. Sleeping until then rather than for a whole
+            // period means we neither drift nor accumulate rounding.
+            let next = start + Duration::from_nanos(n * 1_000_000_000 / 60);
+            thread::sleep(next.saturating_duration_since(Instant::now()));
+            if timers_running.load(Ordering::Relaxed) {
+                // The event loop may have finished while we slept.
+                if timers_tx.send(Event::Tick).is_err() {
+                    return;
+                }
+                if frame_timing {
+                    let clocks = current_speed(&frame_speed, &frame_turbo);
+                    let events = (0..clocks).map(|_| Event::Clock).chain([Event::FrameEnd]);
+                    if events.map(|event| frame_tx.send(event)).any(|r| r.is_err()) {
+                        return;
+                    }
                 }
-                // Return from subroutine.
-                0x00EE => chip8.pc = chip8.stack.pop().expect("returning from no subroutine"),
-                _ => unimplemented!("opcode {current_instruction:#X?}"),
-            },
-            // Jump to NNN immediate.
-            0x1 => chip8.pc = current_instruction & 0x0fff,
-            // Call subroutine at NNN.
-            0x2 => {
-                chip8.stack.push(chip8.pc);
-                chip8.pc = current_instruction & 0x0fff;
             }
-            // Skip if VX == NN.
-            0x3 => {
-                if chip8.rv[nibble!(1)] == current_instruction as u8 {
-                    chip8.pc += 2;
+        }
+    });
+
+    // Otherwise the clock pulses to ensure `hz` instructions are FDE'd per second.
+    let clock_thread = (!frame_timing).then(|| {
+        let clock_tx = event_tx.clone();
+        let clock_speed = Arc::clone(&speed);
+        let clock_turbo = Arc::clone(&turbo);
+        let clock_running = Arc::clone(&running);
+        let clock_shutdown = Arc::clone(&shutdown);
+        thread::spawn(move || {
+            while !clock_shutdown.load(Ordering::Relaxed) {
+                let hz = current_speed(&clock_speed, &clock_turbo);
+                let delay = Duration::from_secs_f64(1.0 / hz as f64);
+                thread::sleep(delay);
+                if clock_running.load(Ordering::Relaxed) && clock_tx.send(Event::Clock).is_err() {
+                    return;
                 }
             }
-            // Skip if VX != NN.
-            0x4 => {
-                if chip8.rv[nibble!(1)] != current_instruction as u8 {
-                    chip8.pc += 2;
+        })
+    });
+
+    // The latest frame is shared with the draw thread, which is woken through `draw_tx` to render
+    // it. Reusing the buffers means drawing doesn't allocate once they're big enough for the high
+    // resolution display, and since at most one wakeup is queued, we only ever render the most
+    // recent frame if we fall behind.
+    let frame = Arc::new(Mutex::new(Frame {
+        display: chip8.display().clone(),
+        overlay: String::new(),
+    }));
+    let (draw_tx, draw_rx) = mpsc::sync_channel(1);
+    let draw_frame = Arc::clone(&frame);
+    // The terminal's size is looked up again after `SIGWINCH` tells us it's been resized, and the
+    // frame redrawn. The signal can't wake the draw thread itself, so the thread checks for it
+    // every `RESIZE_POLL`.
+    const RESIZE_POLL: Duration = Duration::from_millis(50);
+    // Frames are drawn at most `max_fps` times a second, however often the display changes, to
+    // save writing frames to the terminal faster than it shows them.
+    let frame_time = Duration::from_secs_f64(1.0 / args.max_fps as f64);
+    // The overlay goes between the status row and the debugger.
+    let overlay_row = args.render.rows() + 2;
+    // Without the display there's no draw thread, and wakeups sent to it are dropped. The thread
+    // finishes once `draw_tx` is dropped.
+    let draw_thread = terminal_display.then(|| {
+        thread::spawn(move || {
+            let mut display = Display::default();
+            let mut overlay = String::new();
+            input::watch_resizes();
+            let mut size = input::terminal_size().ok();
+            let mut drawn: Option<Instant> = None;
+            loop {
+                let woken = match draw_rx.recv_timeout(RESIZE_POLL) {
+                    Ok(()) => true,
+                    Err(mpsc::RecvTimeoutError::Timeout) => false,
+                    Err(mpsc::RecvTimeoutError::Disconnected) => return,
+                };
+                if input::resized() {
+                    let old = std::mem::replace(&mut size, input::terminal_size().ok());
+                    if !woken && size == old {
+                        continue;
+                    }
+                } else if !woken {
+                    continue;
                 }
+                if let Some(since) = drawn.map(|drawn| drawn.elapsed()) {
+                    thread::sleep(frame_time.saturating_sub(since));
+                }
+                drawn = Some(Instant::now());
+                // The overlay's rows are cleared once after it's hidden.
+                let mut overlay_shown = !overlay.is_empty();
+                {
+                    let frame = draw_frame
+                        .lock()
+                        .expect("main thread doesn't panic holding lock");
+                    display.clone_from(&frame.display);
+                    overlay.clone_from(&frame.overlay);
+                    overlay_shown |= !overlay.is_empty();
+                    // This is the latest frame, so a wakeup sent while we slept is already handled.
+                    let _ = draw_rx.try_recv();
+                }
+                // Hold the lock for the whole frame so that the debugger can't write in the
+                // middle of it. There's nowhere to report a failure to draw.
+                let mut stdout = io::stdout().lock();
+                drop(render::draw(
+                    &mut stdout,
+                    &display,
+                    args.render,
+                    args.theme,
+                    size,
+                ));
+                if overlay_shown {
+                    let mut lines = overlay.lines();
+                    for row in overlay_row..overlay_row + debug::OVERLAY_ROWS {
+                        let line = lines.next().unwrap_or_default();
+                        drop(write!(stdout, "\x1B[{row};1H\x1B[2K{line}"));
+                    }
+                    drop(stdout.flush());
+                }
+            }
+        })
+    });
+
+    // The window draws the latest frame by itself, at the same size as recordings, and sends the
+    // keys pressed in it through `event_tx` like the terminal's.
+    #[cfg(feature = "window")]
+    let window_thread = args.window.then(|| {
+        #[cfg(feature = "meta")]
+        let title = meta.as_ref().and_then(|meta| meta.title.clone());
+        #[cfg(not(feature = "meta"))]
+        let title = None;
+        let title = title
+            .or_else(|| Some(args.rom.file_name()?.to_string_lossy().into_owned()))
+            .unwrap_or_else(|| "stdin".to_owned());
+        let window_frame = Arc::clone(&frame);
+        let latest = move |display: &mut Display| {
+            let frame = window_frame
+                .lock()
+                .expect("main thread doesn't panic holding lock");
+            display.clone_from(&frame.display);
+        };
+        window::spawn(
+            title,
+            args.scale.div_ceil(2),
+            args.theme,
+            args.keys,
+            latest,
+            event_tx.clone(),
+            Arc::clone(&shutdown),
+        )
+        .unwrap_or_else(|e| {
+            eprintln!("error: failed to open a window: {e}");
+            process::exit(1);
+        })
+    });
+
+    // Keyboard input is read on its own thread while the terminal is in raw mode. The previous
+    // terminal mode is restored when `raw_mode` is dropped on returning from `main`.
+    let input_tx = event_tx;
+    let raw_mode = match input::RawMode::enable() {
+        Ok(raw_mode) => {
+            let _input = thread::spawn(move || input::run(input_tx, args.keys, args.key_hold));
+            Some(raw_mode)
+        }
+        Err(e) => {
+            eprintln!("warning: keyboard input disabled: {e}");
+            None
+        }
+    };
+
+    // The beep plays while the sound timer is nonzero. The player is stopped when `beeper` is
+    // dropped on returning from `main`.
+    let beeper = if args.mute || args.beep_bell {
+        None
+    } else {
+        audio::Beeper::spawn()
+            .map_err(|e| {
+                eprintln!("warning: sound disabled: {e}, `--beep-bell` uses the terminal bell")
+            })
+            .ok()
+    };
+    if let Some(beeper) = &beeper {
+        chip8.on_sound(beeper.sound_hook());
+    } else if args.beep_bell && !args.mute {
+        chip8.on_sound(audio::bell_hook());
+    }
+
+    let mut debugger = if args.debug {
+        if raw_mode.is_none() {
+            drop(hidden_cursor);
+            eprintln!("error: the debugger needs keyboard input");
+            process::exit(1);
+        }
+        let debugger = debug::Debugger::new(overlay_row + debug::OVERLAY_ROWS, args.rewind);
+        debugger.show(&chip8);
+        Some(debugger)
+    } else {
+        None
+    };
+
+    // Publish the display to the draw thread.
+    let redraw = |chip8: &Chip8| {
+        let mut frame = frame
+            .lock()
+            .expect("draw thread doesn't panic holding lock");
+        frame.display.clone_from(chip8.display());
+        drop(frame);
+        #[cfg(feature = "record")]
+        if let Some(recorder) = &recorder {
+            recorder.record(chip8.framebuffer(), chip8.width());
+        }
+        // Nothing to do if a wakeup is already pending.
+        let _ = draw_tx.try_send(());
+    };
+    // Publish the registers for the overlay, or clear it when it's turned off.
+    let show_overlay = |chip8: &Chip8, on: bool| {
+        let mut frame = frame
+            .lock()
+            .expect("draw thread doesn't panic holding lock");
+        frame.overlay = if on {
+            debug::overlay(chip8)
+        } else {
+            String::new()
+        };
+        drop(frame);
+        let _ = draw_tx.try_send(());
+    };
+    // Publish a blank display, which ROMs often clear to every frame, by blanking the shared one
+    // rather than copying it.
+    let clear = |chip8: &Chip8| {
+        let mut frame = frame
+            .lock()
+            .expect("draw thread doesn't panic holding lock");
+        let (width, height) = chip8.dimensions();
+        frame.display.resize(width, height);
+        drop(frame);
+        #[cfg(feature = "record")]
+        if let Some(recorder) = &recorder {
+            recorder.record(chip8.framebuffer(), chip8.width());
+        }
+        let _ = draw_tx.try_send(());
+    };
+
+    // Snapshots are saved next to the ROM, or in the working directory if it came from stdin, with
+    // status messages printed below the display.
+    let state_path = if from_stdin {
+        PathBuf::from("stdin.state")
+    } else {
+        let mut path = args.rom.clone().into_os_string();
+        path.push(".state");
+        PathBuf::from(path)
+    };
+    // Screenshots are numbered so that earlier ones aren't overwritten, e.g. `pong-1.png`.
+    let screenshot_path = || {
+        let (dir, stem) = if from_stdin {
+            (Path::new(""), "stdin".into())
+        } else {
+            (
+                args.rom.parent().unwrap_or(Path::new("")),
+                args.rom.file_stem().unwrap_or_default().to_string_lossy(),
+            )
+        };
+        (1..)
+            .map(|n| dir.join(format!("{stem}-{n}.png")))
+            .find(|path| !path.exists())
+            .expect("some number is free")
+    };
+    let status_row = args.render.rows() + 1;
+    let status = |message: &str| {
+        if !terminal_display {
+            if !message.is_empty() {
+                eprintln!("{message}");
             }
-            // Skip if VX == VY.
-            0x5 => {
-                if chip8.rv[nibble!(1)] == chip8.rv[nibble!(2)] {
-                    chip8.pc += 2;
+        } else {
+            print!("\x1B[{status_row};1H\x1B[2K{message}");
+            let _ = io::stdout().flush();
+        }
+    };
+    #[cfg(feature = "meta")]
+    let description = meta.as_ref().and_then(RomMeta::description);
+    #[cfg(not(feature = "meta"))]
+    let description: Option<String> = None;
+    match (&description, &detected) {
+        (Some(description), Some(detected)) => status(&format!("{description}; {detected}")),
+        (Some(message), None) | (None, Some(message)) => status(message),
+        (None, None) => {}
+    }
+
+    // Whether the user has paused the emulator with space.
+    let mut paused = false;
+
+    // Whether the program has finished, with `00FD` or by jumping to itself. The timers keep
+    // running so that a final beep can finish, but no more instructions are executed.
+    let mut halted = false;
+
+    #[cfg(feature = "record")]
+    if let Some(recorder) = &recorder {
+        recorder.record(chip8.framebuffer(), chip8.width());
+    }
+
+    // The number of instructions executed, which key events are recorded and replayed against.
+    let mut cycle = 0;
+
+    // With frame timing, whether the display has been drawn to since the last frame ended.
+    let mut frame_drawn = false;
+
+    // Unknown opcodes skipped with `--skip-unknown`, by address and opcode, with how many times
+    // each was skipped.
+    let mut skipped = BTreeMap::new();
+
+    // Whether the registers are shown below the display, refreshed at most once a frame.
+    let mut overlay = false;
+    let mut overlay_refreshed = Instant::now();
+
+    // Event loop, which ends with an error if the program crashes.
+    let error = loop {
+        let input = match event_rx.recv().expect("timers thread owns a sender") {
+            // A replay ticks the timers at the cycles they were recorded on instead.
+            Event::Tick if replay.is_some() => continue,
+            Event::Tick => {
+                chip8.tick_timers();
+                if let Some(recorder) = &mut input_recorder {
+                    if let Err(e) = recorder.record_tick(cycle) {
+                        status(&format!("input recording stopped: {e}"));
+                        input_recorder = None;
+                    }
                 }
+                continue;
             }
-            // Set register VX to NN.
-            0x6 => chip8.rv[nibble!(1)] = current_instruction as u8,
-            // Add to register VX value NN.
-            0x7 => {
-                let rv = &mut chip8.rv[nibble!(1)];
-                *rv = rv.wrapping_add(current_instruction as u8);
+            Event::Clock => None,
+            Event::FrameEnd => {
+                if std::mem::take(&mut frame_drawn) {
+                    redraw(&chip8);
+                }
+                continue;
             }
-            0x8 => match current_instruction & 0xf {
-                // Set VX to VY.
-                0x0 => chip8.rv[nibble!(1)] = chip8.rv[nibble!(2)],
-                // Set VX = VX | VY.
-                0x1 => chip8.rv[nibble!(1)] = chip8.rv[nibble!(1)] | chip8.rv[nibble!(2)],
-                // Set VX = VX & VY.
-                0x2 => chip8.rv[nibble!(1)] = chip8.rv[nibble!(1)] & chip8.rv[nibble!(2)],
-                // Set VX = VX xor VY.
-                0x3 => chip8.rv[nibble!(1)] = chip8.rv[nibble!(1)] ^ chip8.rv[nibble!(2)],
-                // Set VX = VX + VY and set carry in VF.
-                0x4 => {
-                    let v = chip8.rv[nibble!(1)] as u16 + chip8.rv[nibble!(2)] as u16;
-                    chip8.rv[0xF] = if v > 255 { 1 } else { 0 };
-                    chip8.rv[nibble!(1)] = v as u8;
+            Event::Input(input) => Some(input),
+        };
+        if overlay && overlay_refreshed.elapsed() >= OVERLAY_REFRESH {
+            show_overlay(&chip8, true);
+            overlay_refreshed = Instant::now();
+        }
+        if let Some(input) = input {
+            match input {
+                // The keyboard doesn't drive the keypad until a replay is over.
+                Input::Down(_) | Input::Up(_) if replay.is_some() => {}
+                Input::Down(k) | Input::Up(k) => {
+                    let down = input == Input::Down(k);
+                    chip8.set_key(k, down);
+                    if let Some(recorder) = &mut input_recorder {
+                        let event = replay::KeyEvent {
+                            cycle,
+                            key: k,
+                            down,
+                        };
+                        if let Err(e) = recorder.record(event) {
+                            status(&format!("input recording stopped: {e}"));
+                            input_recorder = None;
+                        }
+                    }
                 }
-                // Set VX = VX - VY and set carry in VF.
-                0x5 => {
-                    chip8.rv[0xF] = if rv!(Y) > rv!(X) { 1 } else { 0 };
-                    rv!(X) = rv!(X).wrapping_sub(rv!(Y));
+                Input::Faster => {
+                    speed.store(faster(speed.load(Ordering::Relaxed)), Ordering::Relaxed);
                 }
-                // VX >>
-                0x6 => {
-                    let x = rv!(X);
-                    rv!(X) = x / 2;
-                    chip8.rv[0xF] = x % 2;
+                Input::Slower => {
+                    speed.store(slower(speed.load(Ordering::Relaxed)), Ordering::Relaxed);
                 }
-                // Set VX = VY - VX and set carry in VF.
-                0x7 => {
-                    chip8.rv[0xF] = if rv!(X) > rv!(Y) { 1 } else { 0 };
-                    rv!(X) = rv!(Y).wrapping_sub(rv!(X));
+                Input::Turbo(held) => turbo.store(held, Ordering::Relaxed),
+                Input::Char(c) => {
+                    if let Some(debugger) = &mut debugger {
+                        if debugger.input(c, &mut chip8) {
+                            halted = false;
+                            redraw(&chip8);
+                        }
+                    }
                 }
-                // VX <<
-                0xE => {
-                    let x = rv!(X);
-                    rv!(X) = x << 1;
-                    chip8.rv[0xF] = if x & 0b1000_0000 > 0 { 1 } else { 0 };
+                // Space is also typed into debugger commands, so only pause when it's not waiting
+                // for one.
+                Input::Pause if debugger.as_ref().is_some_and(|d| d.paused()) => {}
+                Input::Pause => {
+                    paused = !paused;
+                    status(if paused {
+                        "paused, press space to resume"
+                    } else {
+                        ""
+                    });
+                }
+                Input::Reset => {
+                    chip8.reset();
+                    apply_pokes(&mut chip8);
+                    halted = false;
+                    redraw(&chip8);
+                    if let Some(debugger) = debugger.as_ref().filter(|d| d.paused()) {
+                        debugger.show(&chip8);
+                    }
+                    status("reset");
                 }
-                _ => unimplemented!("opcode {current_instruction:#X?}"),
-            },
-            // Skip if VX != VY.
-            0x9 => {
-                if chip8.rv[nibble!(1)] != chip8.rv[nibble!(2)] {
-                    chip8.pc += 2;
+                Input::SaveState => {
+                    let message = match std::fs::write(&state_path, chip8.save_state()) {
+                        Ok(()) => format!("saved state to `{}`", state_path.display()),
+                        Err(e) => format!("failed to save `{}`: {e}", state_path.display()),
+                    };
+                    status(&message);
                 }
+                Input::LoadState => {
+                    let result = std::fs::read(&state_path)
+                        .map_err(|e| e.to_string())
+                        .and_then(|state| chip8.load_state(&state).map_err(|e| e.to_string()));
+                    let message = match result {
+                        Ok(()) => {
+                            halted = false;
+                            redraw(&chip8);
+                            if let Some(debugger) = debugger.as_ref().filter(|d| d.paused()) {
+                                debugger.show(&chip8);
+                            }
+                            format!("loaded state from `{}`", state_path.display())
+                        }
+                        Err(e) => format!("failed to load `{}`: {e}", state_path.display()),
+                    };
+                    status(&message);
+                }
+                Input::Screenshot => {
+                    let path = screenshot_path();
+                    let message = match screenshot::framebuffer_to_png(
+                        chip8.framebuffer(),
+                        chip8.width(),
+                        chip8.height(),
+                        args.scale,
+                        &path,
+                    ) {
+                        Ok(()) => format!("saved screenshot to `{}`", path.display()),
+                        Err(e) => format!("failed to save `{}`: {e}", path.display()),
+                    };
+                    status(&message);
+                }
+                Input::Overlay => {
+                    overlay = !overlay;
+                    show_overlay(&chip8, overlay);
+                }
+                Input::Quit => break None,
             }
-            // Set RI to NNN.
-            0xA => chip8.ri = current_instruction & 0x0fff,
-            // Jump to B0 + NNN.
-            0xB => chip8.pc = chip8.rv[0] as u16 + current_instruction & 0x0fff,
-            // VX = PRNG & NN.
-            0xC => rv!(X) = prng.next() & current_instruction as u8,
-            // Draw DXYN.
-            0xD => {
-                let x = chip8.rv[nibble!(1)] as usize % WIDTH;
-                let y = chip8.rv[nibble!(2)] as usize % HEIGHT;
-                let height = current_instruction & 0xf;
-
-                for (j, row) in (y..y + height as usize).zip(chip8.ri..chip8.ri + height) {
-                    let row = chip8.memory[row as usize];
-                    for (i, x) in (0..8).zip(x..x + 8) {
-                        chip8.display[j * WIDTH + x] ^= (row >> (7 - i) & 0x1) as u8;
-                    }
+        }
+
+        if let Some(debugger) = debugger.as_mut().filter(|d| d.paused()) {
+            running.store(false, Ordering::Relaxed);
+            if !debugger.take_step() {
+                continue;
+            }
+        } else if paused {
+            running.store(false, Ordering::Relaxed);
+            if let Some(beeper) = &beeper {
+                beeper.set_silenced(true);
+            }
+            continue;
+        } else {
+            running.store(true, Ordering::Relaxed);
+            // The beep is silenced while fast forwarding.
+            if let Some(beeper) = &beeper {
+                beeper.set_silenced(turbo.load(Ordering::Relaxed));
+                beeper.set_pattern(chip8.audio_pattern());
+            }
+
+            // Input is handled as soon as it arrives, but instructions wait for the clock.
+            if input.is_some() || halted {
+                continue;
+            }
+
+            if let Some(debugger) = &mut debugger {
+                if debugger.hit_breakpoint(&chip8) {
+                    debugger.show(&chip8);
+                    continue;
                 }
-                draw_tx
-                    .send(chip8.display.clone())
-                    .expect("rx thread loops forever");
             }
-            0xF => match current_instruction as u8 {
-                0x07 => rv!(X) = chip8.delay_timer,
-                0x15 => chip8.delay_timer = rv!(X),
-                0x18 => chip8.sound_timer = rv!(X),
-                _ => unimplemented!("opcode {current_instruction:#X?}"),
-            },
-            _ => unimplemented!("opcode {current_instruction:#X?}"),
         }
+
+        if let Some(log) = &mut replay {
+            for event in log.due(cycle) {
+                chip8.set_key(event.key, event.down);
+            }
+            for _ in 0..log.ticks_due(cycle) {
+                chip8.tick_timers();
+            }
+            if log.finished() {
+                replay = None;
+                status("replay finished");
+            }
+        }
+
+        let pc = chip8.pc();
+        if let Some(tracer) = &mut tracer {
+            tracer.before(&chip8);
+        }
+        if let Some(profile) = &mut profile {
+            profile.before(&chip8);
+        }
+        // The debugger keeps a snapshot from before each instruction, to step back to.
+        if let Some(debugger) = &mut debugger {
+            debugger.snapshot(&chip8);
+        }
+        let mut result = chip8.step();
+        // Time stands still while paused in the debugger, so skip ahead to the next frame rather
+        // than waiting for it.
+        if result == Ok(StepOutcome::WaitingForFrame) && !running.load(Ordering::Relaxed) {
+            chip8.tick_timers();
+            if let Some(recorder) = &mut input_recorder {
+                if let Err(e) = recorder.record_tick(cycle) {
+                    status(&format!("input recording stopped: {e}"));
+                    input_recorder = None;
+                }
+            }
+            result = chip8.step();
+        }
+        if result != Ok(StepOutcome::WaitingForFrame) {
+            cycle += 1;
+            if let Some(debugger) = &mut debugger {
+                debugger.record();
+            }
+        }
+        if let Some(profile) = &mut profile {
+            profile.after(&result);
+        }
+        if let Some(t) = &mut tracer {
+            if let Err(e) = t.after(&chip8, &result) {
+                status(&format!("tracing stopped: {e}"));
+                tracer = None;
+            }
+        }
+        match result {
+            // Frames aren't ending while the debugger is paused, so steps are drawn straight away.
+            Ok(StepOutcome::Draw | StepOutcome::ClearScreen)
+                if frame_timing && running.load(Ordering::Relaxed) =>
+            {
+                frame_drawn = true;
+            }
+            Ok(StepOutcome::Draw) => redraw(&chip8),
+            Ok(StepOutcome::ClearScreen) => clear(&chip8),
+            // An automated run is over once nothing more will be executed.
+            Ok(StepOutcome::Halt) if args.max_cycles.is_some() => break None,
+            Ok(StepOutcome::Halt) => {
+                if !halted {
+                    status("the program has halted, press F1 to restart it or Ctrl-C to quit");
+                }
+                halted = true;
+            }
+            Ok(
+                StepOutcome::Continue
+                | StepOutcome::WaitingForKey(_)
+                | StepOutcome::WaitingForFrame,
+            ) => {}
+            // The opcode is left skipped, so the program carries on after the checkpoint. Test
+            // harnesses read checkpoints from stderr, so they always go there, and are shown on
+            // the status row too when there's a display.
+            Err(Chip8Error::UnknownOpcode { pc, opcode }) if args.test_hook == Some(opcode) => {
+                let checkpoint = debug::checkpoint(&chip8, pc);
+                eprintln!("{checkpoint}");
+                if terminal_display {
+                    status(&checkpoint);
+                }
+            }
+            // While developing a ROM, carry on to see how far it gets.
+            Err(Chip8Error::UnknownOpcode { pc, opcode }) if args.skip_unknown => {
+                let count = skipped.entry((pc, opcode)).or_insert(0);
+                if *count == 0 {
+                    status(&format!("skipped unknown opcode {opcode:04X} at {pc:04X}"));
+                }
+                *count += 1;
+            }
+            Err(e) => break Some(e),
+        }
+
+        if let Some(debugger) = &mut debugger {
+            if let Some(warning) = chip8.take_warning() {
+                debugger.warn(warning);
+            }
+            debugger.check_watchpoints(&chip8, pc);
+            if debugger.paused() {
+                debugger.show(&chip8);
+            }
+        }
+
+        if args.max_cycles.is_some_and(|max| cycle >= max) {
+            break None;
+        }
+    };
+
+    // Stop the other threads, letting the draw thread finish the frame it's drawing so that it
+    // doesn't write over the terminal once it's restored. The input thread is left blocked
+    // reading the keyboard, and returns by itself once it finds that `event_rx` has gone.
+    shutdown.store(true, Ordering::Relaxed);
+    drop(draw_tx);
+    for thread in [Some(timers_thread), clock_thread, draw_thread]
+        .into_iter()
+        .flatten()
+    {
+        thread.join().expect("thread doesn't panic");
+    }
+    #[cfg(feature = "window")]
+    if let Some(thread) = window_thread {
+        thread.join().expect("thread doesn't panic");
+    }
+
+    #[cfg(feature = "record")]
+    let recorded = recorder.map(|recorder| recorder.finish());
+    // `process::exit` doesn't run destructors, so restore the terminal first.
+    drop(raw_mode);
+    drop(hidden_cursor);
+    drop(beeper);
+    drop(tracer);
+    drop(input_recorder);
+    if terminal_display {
+        print!("\x1B[{status_row};1H");
+        let _ = io::stdout().flush();
+    }
+    #[cfg(feature = "record")]
+    if let Some(Err(e)) = recorded {
+        eprintln!(
+            "error: failed to write `{}`: {e}",
+            args.record.unwrap().display()
+        );
+    }
+    if args.dump_state {
+        eprint!("{}", debug::dump(&chip8));
+    }
+    if let Some(profile) = profile {
+        eprint!("{}", profile.report());
+    }
+    if !skipped.is_empty() {
+        eprintln!("skipped {} unknown opcodes:", skipped.len());
+        for ((pc, opcode), count) in skipped {
+            eprintln!("{pc:04X}  {opcode:04X}  {count} times");
+        }
+    }
+    if let Some(e) = error {
+        eprintln!("error: {e}");
+        process::exit(1);
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+
     #[test]
-    fn init_memory() {
-        drop(super::Chip8::new());
+    fn speed_keys() {
+        assert_eq!(faster(700), 770);
+        assert_eq!(faster(5), 6);
+        assert_eq!(slower(700), 630);
+        assert_eq!(slower(1), 1);
+        // Pressing `+` over and over stops at the cap rather than overflowing.
+        let mut speed = chip8::DEFAULT_HZ;
+        for _ in 0..1000 {
+            speed = faster(speed);
+        }
+        assert_eq!(speed, MAX_SPEED);
+        // A faster clock from the command line is left alone rather than overflowed or slowed.
+        assert_eq!(faster(u32::MAX), u32::MAX);
+        assert_eq!(faster(MAX_SPEED + 1), MAX_SPEED + 1);
     }
 }
@@ -0,0 +1,218 @@
+//! Metadata distributed alongside a ROM, so that players don't need to know which options it
+//! needs. It's read from a JSON file with the same name as the ROM, e.g. `pong.json` next to
+//! `pong.ch8`:
+//!
+//! ```json
+//! {
+//!     "title": "Pong",
+//!     "quirks": "superchip",
+//!     "hz": 1000,
+//!     "keys": { "1": "left paddle up", "4": "left paddle down" }
+//! }
+//! ```
+//!
+//! Every field is optional and unknown fields are ignored. The settings are only defaults, which
+//! options given on the command line override.
+
+use std::{collections::HashMap, fs, io, path::Path, str::FromStr};
+
+use chip8::Quirks;
+use serde::Deserialize;
+
+use crate::args::Args;
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct RomMeta {
+    pub title: Option<String>,
+    /// A quirks preset, given by name.
+    pub quirks: Option<Quirks>,
+    pub hz: Option<u32>,
+    /// What the game uses keypad keys for, in key order.
+    pub keys: Vec<(u8, String)>,
+}
+
+impl RomMeta {
+    /// Read the metadata for the ROM at `rom`, or `None` if it doesn't have any.
+    pub fn for_rom(rom: &Path) -> Result<Option<Self>, String> {
+        let path = rom.with_extension("json");
+        if path == rom {
+            return Ok(None);
+        }
+        match fs::read_to_string(&path) {
+            Ok(json) => json
+                .parse()
+                .map(Some)
+                .map_err(|e| format!("invalid `{}`: {e}", path.display())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(format!("failed to read `{}`: {e}", path.display())),
+        }
+    }
+
+    /// Use the settings in `args` where the command line didn't give them.
+    pub fn apply(&self, args: &mut Args) {
+        if let Some(preset) = self.quirks.filter(|_| !args.quirks_given) {
+            args.use_preset(preset);
+        }
+        // The clock rate doesn't apply when frame timing was asked for.
+        if let Some(hz) = self
+            .hz
+            .filter(|_| !args.hz_given && args.cycles_per_frame.is_none())
+        {
+            args.hz = hz;
+        }
+    }
+
+    /// The title and what the keys do, to show when the game starts.
+    pub fn description(&self) -> Option<String> {
+        let keys: Vec<_> = self
+            .keys
+            .iter()
+            .map(|(key, label)| format!("{key:X}: {label}"))
+            .collect();
+        match (&self.title, keys.is_empty()) {
+            (None, true) => None,
+            (Some(title), true) => Some(title.clone()),
+            (None, false) => Some(format!("keys {}", keys.join(", "))),
+            (Some(title), false) => Some(format!("{title}, keys {}", keys.join(", "))),
+        }
+    }
+}
+
+impl FromStr for RomMeta {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let sidecar: Sidecar = serde_json::from_str(s).map_err(|e| e.to_string())?;
+        let quirks = sidecar
+            .quirks
+            .map(|quirks| {
+                quirks
+                    .parse()
+                    .map_err(|()| format!("unknown quirks preset `{quirks}`"))
+            })
+            .transpose()?;
+        if sidecar.hz == Some(0) {
+            return Err("invalid value for `hz`".to_owned());
+        }
+        let mut keys = sidecar
+            .keys
+            .into_iter()
+            .map(|(key, label)| {
+                u8::from_str_radix(&key, 16)
+                    .ok()
+                    .filter(|&k| k < 16)
+                    .map(|k| (k, label))
+                    .ok_or_else(|| format!("invalid key `{key}`"))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        keys.sort();
+        Ok(Self {
+            title: sidecar.title,
+            quirks,
+            hz: sidecar.hz,
+            keys,
+        })
+    }
+}
+
+/// The file as written, before the quirks and keys are checked. Unknown fields are ignored.
+#[derive(Deserialize)]
+struct Sidecar {
+    title: Option<String>,
+    quirks: Option<String>,
+    hz: Option<u32>,
+    #[serde(default)]
+    keys: HashMap<String, String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sidecar() {
+        let meta: RomMeta = r#"{
+            "title": "Pong \"2\"",
+            "quirks": "superchip",
+            "hz": 1000,
+            "keys": { "4": "left paddle down", "1": "left paddle up" },
+            "author": { "name": "someone", "years": [1990, 1991], "alive": true, "url": null }
+        }"#
+        .parse()
+        .unwrap();
+        assert_eq!(
+            meta,
+            RomMeta {
+                title: Some("Pong \"2\"".to_owned()),
+                quirks: Some(Quirks::superchip()),
+                hz: Some(1000),
+                keys: vec![
+                    (1, "left paddle up".to_owned()),
+                    (4, "left paddle down".to_owned())
+                ],
+            }
+        );
+        assert_eq!(
+            meta.description().unwrap(),
+            "Pong \"2\", keys 1: left paddle up, 4: left paddle down"
+        );
+        assert_eq!("{}".parse::<RomMeta>().unwrap().description(), None);
+    }
+
+    #[test]
+    fn apply() {
+        let parse = |args: &[&str]| Args::parse(args.iter().map(|&arg| arg.to_owned())).unwrap();
+        let meta: RomMeta = r#"{ "quirks": "xochip", "hz": 1000 }"#.parse().unwrap();
+        let mut args = parse(&["--stack-depth=12", "a.ch8"]);
+        meta.apply(&mut args);
+        assert_eq!(
+            (args.quirks, args.hz),
+            (
+                Quirks {
+                    stack_depth: 12,
+                    ..Quirks::xochip()
+                },
+                1000
+            )
+        );
+        // Options given on the command line take precedence.
+        let mut args = parse(&["--accurate", "--hz=500", "a.ch8"]);
+        meta.apply(&mut args);
+        assert_eq!((args.quirks, args.hz), (Quirks::chip8(), 500));
+        let mut args = parse(&["--quirks=superchip", "--frame-timing", "a.ch8"]);
+        meta.apply(&mut args);
+        assert_eq!(
+            (args.quirks, args.hz),
+            (Quirks::superchip(), chip8::DEFAULT_HZ)
+        );
+    }
+
+    #[test]
+    fn invalid_sidecars() {
+        for json in [
+            "",
+            "[]",
+            "{\"hz\": 1000",
+            "{\"hz\": 0}",
+            "{\"hz\": \"fast\"}",
+            "{\"quirks\": \"chip48\"}",
+            "{\"keys\": {\"G\": \"fire\"}}",
+            "{\"title\": \"Pong\"} {}",
+            "{\"title\": \"Pong}",
+        ] {
+            assert!(json.parse::<RomMeta>().is_err(), "{json}");
+        }
+        // Deeply nested values don't overflow the stack: they're skipped in ignored fields and an
+        // error elsewhere.
+        let nested = |field: &str| {
+            let depth = 200_000;
+            format!(
+                "{{\"{field}\": {}{}}}",
+                "[".repeat(depth),
+                "]".repeat(depth)
+            )
+        };
+        assert_eq!(nested("author").parse(), Ok(RomMeta::default()));
+        assert!(nested("keys").parse::<RomMeta>().is_err());
+    }
+}
@@ -0,0 +1,91 @@
+//! Counting how often each opcode is executed, to show what a ROM spends its time on.
+
+use std::{collections::HashMap, fmt::Write as _};
+
+use chip8::{disasm, Chip8, Chip8Error, StepOutcome};
+
+pub struct Profile {
+    /// Executions of each opcode, indexed by the opcode itself so that counting is cheap. They're
+    /// grouped by pattern for the report.
+    counts: Box<[u64]>,
+    /// The opcode about to be executed.
+    opcode: u16,
+}
+
+impl Profile {
+    pub fn new() -> Self {
+        Self {
+            counts: vec![0; 0x10000].into_boxed_slice(),
+            opcode: 0,
+        }
+    }
+
+    /// Remember the opcode `chip8` is about to execute.
+    pub fn before(&mut self, chip8: &Chip8) {
+        let pc = chip8.pc() as usize;
+        self.opcode = match chip8.memory().get(pc..pc + 2) {
+            Some(&[hi, lo]) => u16::from_be_bytes([hi, lo]),
+            _ => 0,
+        };
+    }
+
+    /// Count the instruction just executed, unless it failed or was held up waiting for a frame.
+    pub fn after(&mut self, result: &Result<StepOutcome, Chip8Error>) {
+        if matches!(result, Ok(outcome) if *outcome != StepOutcome::WaitingForFrame) {
+            self.counts[self.opcode as usize] += 1;
+        }
+    }
+
+    /// A table of each pattern executed, such as `DXYN`, with how many times it was executed and
+    /// its share of all instructions, most executed first.
+    pub fn report(&self) -> String {
+        let mut patterns = HashMap::new();
+        for (opcode, &count) in self.counts.iter().enumerate().filter(|&(_, &n)| n > 0) {
+            let pattern = disasm::pattern(opcode as u16).expect("executed opcodes are supported");
+            *patterns.entry(pattern).or_insert(0) += count;
+        }
+        let mut patterns: Vec<(&str, u64)> = patterns.into_iter().collect();
+        patterns.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        let total: u64 = patterns.iter().map(|&(_, count)| count).sum();
+
+        let mut report = format!("opcode {:>12}  share\n", "count");
+        for (pattern, count) in patterns {
+            let share = count as f64 / total as f64 * 100.0;
+            writeln!(report, "{pattern:<6} {count:>12} {share:>5.1}%").unwrap();
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chip8::Quirks;
+
+    use super::*;
+
+    #[test]
+    fn report() {
+        // Count down V0 from 3, then halt.
+        let mut chip8 = Chip8::new(Quirks::default());
+        chip8
+            .load_rom(&[0x60, 0x03, 0x70, 0xFF, 0x30, 0x00, 0x12, 0x02, 0x12, 0x08])
+            .unwrap();
+        let mut profile = Profile::new();
+        loop {
+            profile.before(&chip8);
+            let result = chip8.step();
+            profile.after(&result);
+            if result == Ok(StepOutcome::Halt) {
+                break;
+            }
+        }
+        assert_eq!(
+            profile.report(),
+            "opcode        count  share\n\
+             1NNN              3  30.0%\n\
+             3XNN              3  30.0%\n\
+             7XNN              3  30.0%\n\
+             6XNN              1  10.0%\n"
+        );
+    }
+}
@@ -0,0 +1,268 @@
+//! Behaviours which differ between CHIP-8 platforms.
+
+use core::str::FromStr;
+
+/// The presets by name.
+fn presets() -> [(&'static str, Quirks); 3] {
+    [
+        ("chip8", Quirks::chip8()),
+        ("superchip", Quirks::superchip()),
+        ("xochip", Quirks::xochip()),
+    ]
+}
+
+/// Games which need a particular preset, as the FNV-1a hash of the ROM, the game's title and the
+/// preset's name. The ROMs are the ones from John Earnest's chip8Archive, copies of which are in
+/// `tests/roms`.
+const KNOWN_ROMS: &[(u64, &str, &str)] = &[
+    (0xB80F_C5EB_E4F4_3FC2, "The Binding of COSMAC", "superchip"),
+    (0x1EDC_D85D_546C_29F3, "Knight", "superchip"),
+    (0x49A8_C744_0BE3_4366, "Super Square", "superchip"),
+    (0x3ECC_15C3_2456_6594, "Alien Inv8sion", "xochip"),
+    (0x0B58_E2C0_036C_6811, "Sub8", "xochip"),
+    (0x1BC4_C947_AF15_E77A, "Super NeatBoy", "xochip"),
+];
+
+/// Switches for opcodes whose behaviour differs between platforms. The presets match the ones
+/// checked by `5-quirks.ch8` from Timendus' <https://github.com/Timendus/chip8-test-suite>.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    /// `8XY1`, `8XY2` and `8XY3` reset VF to 0 (COSMAC VIP).
+    pub vf_reset: bool,
+    /// `FX55` and `FX65` increment I by X + 1 (COSMAC VIP) rather than leaving it unchanged.
+    pub memory_increment: bool,
+    /// `8XY6` and `8XYE` shift VX in place (CHIP-48/SUPER-CHIP) rather than shifting VY into VX.
+    pub shift: bool,
+    /// `BXNN` jumps to VX + XNN (CHIP-48/SUPER-CHIP) rather than `BNNN` to V0 + NNN.
+    pub jump_vx: bool,
+    /// `DXYN` wraps sprites around the edges of the screen (XO-CHIP) rather than clipping.
+    pub wrap_sprites: bool,
+    /// `FX1E` sets VF when I overflows past 0x0FFF (Amiga interpreter).
+    pub index_overflow: bool,
+    /// `DXYN` waits for the next 60Hz frame (COSMAC VIP), so programs draw at most once a frame.
+    pub display_wait: bool,
+    /// Bytes of memory, 4KB except for XO-CHIP's 64KB.
+    pub memory_size: usize,
+    /// Where programs are loaded and start executing, 0x200 except on rarer platforms such as the
+    /// ETI 660 (0x600).
+    pub load_address: usize,
+    /// How many subroutine calls can be nested before `2NNN` fails, 16 as on most interpreters
+    /// (the COSMAC VIP had room for 12).
+    pub stack_depth: usize,
+    /// The ROM has the two bytes of every instruction swapped, as some tools dump them, so they're
+    /// swapped back as it's loaded. This isn't the behaviour of any real platform, only a way to
+    /// run such ROMs without fixing them first.
+    pub byte_swap: bool,
+}
+
+impl Quirks {
+    /// The original COSMAC VIP interpreter, the most accurate preset: logic opcodes reset VF,
+    /// shifts read VY, `FX55` and `FX65` increment I and drawing waits for the display.
+    pub fn chip8() -> Self {
+        Self {
+            vf_reset: true,
+            memory_increment: true,
+            shift: false,
+            jump_vx: false,
+            wrap_sprites: false,
+            index_overflow: false,
+            display_wait: true,
+            memory_size: 0x1000,
+            load_address: 0x200,
+            stack_depth: 16,
+            byte_swap: false,
+        }
+    }
+
+    /// SUPER-CHIP 1.1 on the HP 48, as most SUPER-CHIP games expect.
+    pub fn superchip() -> Self {
+        Self {
+            vf_reset: false,
+            memory_increment: false,
+            shift: true,
+            jump_vx: true,
+            wrap_sprites: false,
+            index_overflow: false,
+            display_wait: false,
+            memory_size: 0x1000,
+            load_address: 0x200,
+            stack_depth: 16,
+            byte_swap: false,
+        }
+    }
+
+    /// XO-CHIP, as implemented by Octo.
+    pub fn xochip() -> Self {
+        Self {
+            vf_reset: false,
+            memory_increment: true,
+            shift: false,
+            jump_vx: false,
+            wrap_sprites: true,
+            index_overflow: false,
+            display_wait: false,
+            memory_size: 0x10000,
+            load_address: 0x200,
+            stack_depth: 16,
+            byte_swap: false,
+        }
+    }
+
+    /// The name of the preset these quirks match, ignoring the memory layout, stack depth and byte
+    /// swapping.
+    pub fn preset_name(&self) -> Option<&'static str> {
+        presets().into_iter().find_map(|(name, preset)| {
+            let preset = Self {
+                memory_size: self.memory_size,
+                load_address: self.load_address,
+                stack_depth: self.stack_depth,
+                byte_swap: self.byte_swap,
+                ..preset
+            };
+            (preset == *self).then_some(name)
+        })
+    }
+
+    /// The switch called `name`, which is its field's name with dashes, e.g. `vf-reset` for
+    /// [`Quirks::vf_reset`].
+    pub fn switch_mut(&mut self, name: &str) -> Option<&mut bool> {
+        Some(match name {
+            "vf-reset" => &mut self.vf_reset,
+            "memory-increment" => &mut self.memory_increment,
+            "shift" => &mut self.shift,
+            "jump-vx" => &mut self.jump_vx,
+            "wrap-sprites" => &mut self.wrap_sprites,
+            "index-overflow" => &mut self.index_overflow,
+            "display-wait" => &mut self.display_wait,
+            _ => return None,
+        })
+    }
+
+    /// Look `rom` up in a table of known games, returning the game's title and the quirks it
+    /// needs.
+    pub fn detect(rom: &[u8]) -> Option<(&'static str, Self)> {
+        let hash = fnv1a(rom);
+        KNOWN_ROMS
+            .iter()
+            .find(|&&(known, _, _)| known == hash)
+            .map(|&(_, title, preset)| (title, preset.parse().expect("known preset")))
+    }
+}
+
+/// The 64-bit FNV-1a hash of `data`.
+pub(crate) fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xCBF2_9CE4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01B3)
+    })
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Self::chip8()
+    }
+}
+
+impl FromStr for Quirks {
+    type Err = ();
+
+    /// Parse the name of a preset: `chip8`, `superchip` or `xochip`.
+    fn from_str(s: &str) -> Result<Self, ()> {
+        presets()
+            .into_iter()
+            .find(|&(name, _)| name == s)
+            .map(|(_, preset)| preset)
+            .ok_or(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn switches() {
+        let mut quirks = Quirks::chip8();
+        *quirks.switch_mut("vf-reset").unwrap() = false;
+        *quirks.switch_mut("wrap-sprites").unwrap() = true;
+        assert_eq!(
+            quirks,
+            Quirks {
+                vf_reset: false,
+                wrap_sprites: true,
+                ..Quirks::chip8()
+            }
+        );
+        // Only the switches, not the memory layout.
+        assert_eq!(quirks.switch_mut("byte-swap"), None);
+        assert_eq!(quirks.switch_mut("vf_reset"), None);
+    }
+
+    #[test]
+    fn preset_names() {
+        for (name, preset) in presets() {
+            assert_eq!(name.parse(), Ok(preset));
+            assert_eq!(preset.preset_name(), Some(name));
+        }
+        let quirks = Quirks {
+            load_address: 0x600,
+            stack_depth: 12,
+            ..Quirks::superchip()
+        };
+        assert_eq!(quirks.preset_name(), Some("superchip"));
+        let quirks = Quirks {
+            shift: true,
+            ..Quirks::chip8()
+        };
+        assert_eq!(quirks.preset_name(), None);
+    }
+
+    #[test]
+    fn known_roms() {
+        assert_eq!(fnv1a(b""), 0xCBF2_9CE4_8422_2325);
+        assert_eq!(fnv1a(b"a"), 0xAF63_DC4C_8601_EC8C);
+        // Games which run as plain CHIP-8 aren't in the table.
+        assert_eq!(Quirks::detect(include_bytes!("../IBM_Logo.ch8")), None);
+        for (_, _, preset) in KNOWN_ROMS {
+            assert!(preset.parse::<Quirks>().is_ok(), "{preset}");
+        }
+        let roms: [(&[u8], _, _); 6] = [
+            (
+                include_bytes!("../tests/roms/binding.ch8"),
+                "The Binding of COSMAC",
+                Quirks::superchip(),
+            ),
+            (
+                include_bytes!("../tests/roms/knight.ch8"),
+                "Knight",
+                Quirks::superchip(),
+            ),
+            (
+                include_bytes!("../tests/roms/supersquare.ch8"),
+                "Super Square",
+                Quirks::superchip(),
+            ),
+            (
+                include_bytes!("../tests/roms/alien_inv8sion.ch8"),
+                "Alien Inv8sion",
+                Quirks::xochip(),
+            ),
+            (
+                include_bytes!("../tests/roms/sub8.ch8"),
+                "Sub8",
+                Quirks::xochip(),
+            ),
+            (
+                include_bytes!("../tests/roms/super_neatboy.ch8"),
+                "Super NeatBoy",
+                Quirks::xochip(),
+            ),
+        ];
+        for (rom, title, quirks) in roms {
+            assert_eq!(Quirks::detect(rom), Some((title, quirks)), "{title}");
+            // A ROM differing by a single byte, such as another version of the game, isn't.
+            let mut changed = rom.to_vec();
+            changed[0] ^= 1;
+            assert_eq!(Quirks::detect(&changed), None, "{title}");
+        }
+    }
+}
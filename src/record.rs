@@ -0,0 +1,306 @@
+//! Recording the display to an animated GIF.
+//!
+//! Frames are encoded on their own thread so that recording doesn't hold up emulation. GIF frame
+//! delays are in hundredths of a second, so frames drawn less than two hundredths apart (faster
+//! than browsers will play them) are merged, keeping the last.
+
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    sync::mpsc::{self, Sender},
+    thread::{self, JoinHandle},
+    time::Instant,
+};
+
+use chip8::{HIRES_HEIGHT, HIRES_WIDTH};
+
+/// The shortest frame delay in hundredths of a second which players respect.
+const MIN_DELAY: u64 = 2;
+
+/// Bits per pixel for the LZW encoding. GIF doesn't allow 1, even for a 2 colour image.
+const MIN_CODE_SIZE: u32 = 2;
+const CLEAR: u16 = 1 << MIN_CODE_SIZE;
+const END: u16 = CLEAR + 1;
+const MAX_CODES: u16 = 1 << 12;
+
+/// A frame and when it was drawn.
+struct Frame {
+    time: Instant,
+    width: usize,
+    pixels: Vec<u8>,
+}
+
+pub struct Recorder {
+    tx: Sender<Frame>,
+    encoder: JoinHandle<io::Result<()>>,
+}
+
+impl Recorder {
+    /// Start recording to a GIF file at `path`, drawing each pixel of the high resolution display
+    /// as a `scale` by `scale` square and low resolution pixels twice that size. GIFs can be at
+    /// most 65535 pixels wide, so large scales are an error.
+    pub fn create(path: &Path, scale: usize) -> io::Result<Self> {
+        let size = |pixels: usize| {
+            pixels
+                .checked_mul(scale)
+                .and_then(|size| u16::try_from(size).ok())
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "the scale is too large for a GIF, which can be at most 65535 pixels wide",
+                    )
+                })
+        };
+        let (width, height) = (size(HIRES_WIDTH)?, size(HIRES_HEIGHT)?);
+        let mut out = BufWriter::new(File::create(path)?);
+        write_header(&mut out, width, height)?;
+        let (width, height) = (width as usize, height as usize);
+
+        let (tx, rx) = mpsc::channel::<Frame>();
+        let encoder = thread::spawn(move || {
+            let start = Instant::now();
+            let hundredths = |time: Instant| (time - start).as_millis() as u64 / 10;
+            // The latest frame, which is written once we know how long it was shown for.
+            let mut pending: Option<(u64, Frame)> = None;
+            for frame in rx {
+                let time = hundredths(frame.time);
+                pending = match pending {
+                    Some((shown, last)) if time - shown >= MIN_DELAY => {
+                        write_frame(&mut out, &last, width, height, time - shown)?;
+                        Some((time, frame))
+                    }
+                    Some((shown, _)) => Some((shown, frame)),
+                    None => Some((time, frame)),
+                };
+            }
+            // The sender has hung up, so the last frame was shown until now.
+            if let Some((shown, last)) = pending {
+                let delay = (hundredths(Instant::now()) - shown).max(MIN_DELAY);
+                write_frame(&mut out, &last, width, height, delay)?;
+            }
+            out.write_all(&[0x3B])?;
+            out.flush()
+        });
+        Ok(Self { tx, encoder })
+    }
+
+    /// Add `display`, which is `width` pixels wide, as the next frame.
+    pub fn record(&self, display: &[u8], width: usize) {
+        // If the encoder has stopped, the error is reported by `finish`.
+        let _ = self.tx.send(Frame {
+            time: Instant::now(),
+            width,
+            pixels: display.to_vec(),
+        });
+    }
+
+    /// Write the remaining frames and finish the file.
+    pub fn finish(self) -> io::Result<()> {
+        drop(self.tx);
+        self.encoder.join().expect("encoder doesn't panic")
+    }
+}
+
+/// Write the GIF header for a looping black and white animation.
+fn write_header(out: &mut impl Write, width: u16, height: u16) -> io::Result<()> {
+    out.write_all(b"GIF89a")?;
+    out.write_all(&width.to_le_bytes())?;
+    out.write_all(&height.to_le_bytes())?;
+    // A global colour table of 2 colours, background colour 0 and square pixels.
+    out.write_all(&[0x80, 0, 0])?;
+    out.write_all(&[0x00, 0x00, 0x00, 0xFF, 0xFF, 0xFF])?;
+    // The Netscape extension, to loop forever.
+    out.write_all(&[0x21, 0xFF, 0x0B])?;
+    out.write_all(b"NETSCAPE2.0")?;
+    out.write_all(&[0x03, 0x01, 0x00, 0x00, 0x00])
+}
+
+/// Write `frame`, scaled up to `width` by `height`, to be shown for `delay` hundredths of a second.
+fn write_frame(
+    out: &mut impl Write,
+    frame: &Frame,
+    width: usize,
+    height: usize,
+    delay: u64,
+) -> io::Result<()> {
+    let delay = delay.min(u16::MAX as u64) as u16;
+    out.write_all(&[0x21, 0xF9, 0x04, 0x00])?;
+    out.write_all(&delay.to_le_bytes())?;
+    out.write_all(&[0x00, 0x00])?;
+
+    out.write_all(&[0x2C, 0, 0, 0, 0])?;
+    out.write_all(&(width as u16).to_le_bytes())?;
+    out.write_all(&(height as u16).to_le_bytes())?;
+    out.write_all(&[0x00])?;
+
+    let size = width / frame.width;
+    let pixels = (0..height).flat_map(|y| {
+        let row = &frame.pixels[y / size * frame.width..][..frame.width];
+        (0..width).map(move |x| (row[x / size] != 0) as u8)
+    });
+    out.write_all(&[MIN_CODE_SIZE as u8])?;
+    for block in lzw(pixels).chunks(255) {
+        out.write_all(&[block.len() as u8])?;
+        out.write_all(block)?;
+    }
+    out.write_all(&[0x00])
+}
+
+/// Variable width codes packed least significant bit first.
+struct Codes {
+    out: Vec<u8>,
+    bits: u32,
+    len: u32,
+    size: u32,
+}
+
+impl Codes {
+    fn write(&mut self, code: u16) {
+        self.bits |= (code as u32) << self.len;
+        self.len += self.size;
+        while self.len >= 8 {
+            self.out.push(self.bits as u8);
+            self.bits >>= 8;
+            self.len -= 8;
+        }
+    }
+}
+
+/// LZW compress `pixels`, each of which is 0 or 1, as GIF image data.
+fn lzw(pixels: impl Iterator<Item = u8>) -> Vec<u8> {
+    let mut codes = Codes {
+        out: Vec::new(),
+        bits: 0,
+        len: 0,
+        size: MIN_CODE_SIZE + 1,
+    };
+    // The code for each string followed by each pixel, or 0 if there isn't one yet. No string's
+    // code is 0, since the first codes are the single pixels.
+    let mut table = vec![[0u16; 1 << MIN_CODE_SIZE]; MAX_CODES as usize];
+    let mut next = END + 1;
+    codes.write(CLEAR);
+
+    let mut string = None;
+    for pixel in pixels {
+        let Some(prefix) = string else {
+            string = Some(pixel as u16);
+            continue;
+        };
+        match table[prefix as usize][pixel as usize] {
+            0 => {
+                codes.write(prefix);
+                if next >= 1 << codes.size && codes.size < 12 {
+                    codes.size += 1;
+                }
+                if next < MAX_CODES {
+                    table[prefix as usize][pixel as usize] = next;
+                    next += 1;
+                } else {
+                    codes.write(CLEAR);
+                    table.fill([0; 1 << MIN_CODE_SIZE]);
+                    next = END + 1;
+                    codes.size = MIN_CODE_SIZE + 1;
+                }
+                string = Some(pixel as u16);
+            }
+            code => string = Some(code),
+        }
+    }
+    if let Some(prefix) = string {
+        codes.write(prefix);
+        if next >= 1 << codes.size && codes.size < 12 {
+            codes.size += 1;
+        }
+    }
+    codes.write(END);
+    if codes.len > 0 {
+        codes.out.push(codes.bits as u8);
+    }
+    codes.out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Decode GIF image data, as a player would.
+    fn unlzw(data: &[u8]) -> Vec<u8> {
+        let mut bits = data.iter().flat_map(|b| (0..8).map(move |i| b >> i & 1));
+        let mut size = MIN_CODE_SIZE + 1;
+        let mut read =
+            |size| (0..size).fold(0, |code, i| code | (bits.next().unwrap() as usize) << i);
+        let mut table: Vec<Vec<u8>> = Vec::new();
+        let mut last: Option<Vec<u8>> = None;
+        let mut pixels = Vec::new();
+        loop {
+            let code = read(size);
+            if code == CLEAR as usize {
+                table = (0..CLEAR as u8 + 2).map(|p| vec![p]).collect();
+                size = MIN_CODE_SIZE + 1;
+                last = None;
+                continue;
+            }
+            if code == END as usize {
+                return pixels;
+            }
+            let string = match (table.get(code), &last) {
+                (Some(string), _) => string.clone(),
+                (None, Some(last)) => [last.as_slice(), &last[..1]].concat(),
+                (None, None) => panic!("unknown code {code}"),
+            };
+            pixels.extend_from_slice(&string);
+            if let Some(last) = last {
+                if table.len() < MAX_CODES as usize {
+                    table.push([last.as_slice(), &string[..1]].concat());
+                }
+            }
+            if table.len() == 1 << size && size < 12 {
+                size += 1;
+            }
+            last = Some(string);
+        }
+    }
+
+    #[test]
+    fn lzw_round_trip() {
+        // Enough noise to fill the table a few times over, then long runs.
+        let mut lfsr = chip8::Lfsr::with_seed(0xFF);
+        let mut pixels: Vec<u8> = (0..100_000).map(|_| lfsr.next_byte() & 1).collect();
+        pixels.extend((0..10_000).map(|i| (i / 700 % 2) as u8));
+        assert_eq!(unlzw(&lzw(pixels.iter().copied())), pixels);
+        assert_eq!(unlzw(&lzw([1].into_iter())), [1]);
+        assert!(unlzw(&lzw([].into_iter())).is_empty());
+    }
+
+    #[test]
+    fn too_large() {
+        let path = std::env::temp_dir().join("chip8-too-large.gif");
+        let e = Recorder::create(&path, 1100)
+            .err()
+            .expect("65535 pixels at most");
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn frame() {
+        let frame = Frame {
+            time: Instant::now(),
+            width: 2,
+            pixels: vec![1, 0, 0, 1],
+        };
+        let mut gif = Vec::new();
+        write_frame(&mut gif, &frame, 4, 4, 5).unwrap();
+        // The delay, then the image descriptor.
+        assert_eq!(gif[..8], [0x21, 0xF9, 0x04, 0x00, 5, 0, 0x00, 0x00]);
+        assert_eq!(gif[8..18], [0x2C, 0, 0, 0, 0, 4, 0, 4, 0, 0x00]);
+        assert_eq!(gif[18], MIN_CODE_SIZE as u8);
+        let len = gif[19] as usize;
+        assert_eq!(gif.len(), 20 + len + 1);
+        assert_eq!(
+            unlzw(&gif[20..20 + len]),
+            [1, 1, 0, 0, 1, 1, 0, 0, 0, 0, 1, 1, 0, 0, 1, 1]
+        );
+    }
+}
@@ -0,0 +1,455 @@
+//! Rendering the display to the terminal.
+
+use std::{
+    io::{self, Write},
+    str::FromStr,
+};
+
+use chip8::{Display, HIRES_HEIGHT};
+
+/// The most frames drawn per second unless configured otherwise, matching most displays.
+pub const DEFAULT_MAX_FPS: u32 = 60;
+
+const RESET_CURSOR: &str = "\x1B[1;1H";
+/// Go back to the terminal's colours, so that a theme doesn't leak past the display.
+const RESET_COLOURS: &str = "\x1B[0m";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderMode {
+    /// Two pixels per character cell using the upper and lower half block characters.
+    HalfBlock,
+    /// One pixel per character cell using `#`.
+    Ascii,
+}
+
+impl RenderMode {
+    /// Number of terminal rows taken up by a display `height` pixels tall.
+    fn rows_for(self, height: usize) -> usize {
+        match self {
+            Self::HalfBlock => height / 2,
+            Self::Ascii => height,
+        }
+    }
+
+    /// Number of terminal rows set aside for the display, enough for it in either resolution.
+    pub fn rows(self) -> usize {
+        self.rows_for(HIRES_HEIGHT)
+    }
+}
+
+impl FromStr for RenderMode {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "halfblock" => Ok(Self::HalfBlock),
+            "ascii" => Ok(Self::Ascii),
+            _ => Err(()),
+        }
+    }
+}
+
+/// A colour, either from the terminal's 256 colour palette or in true colour.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Colour {
+    Ansi(u8),
+    Rgb(u8, u8, u8),
+}
+
+impl Colour {
+    /// The closest colour in the terminal's 256 colour palette, from its 6x6x6 colour cube or its
+    /// greyscale ramp.
+    pub fn ansi(self) -> u8 {
+        let (r, g, b) = match self {
+            Self::Ansi(n) => return n,
+            Self::Rgb(r, g, b) => (r as i32, g as i32, b as i32),
+        };
+        let distance =
+            |(r2, g2, b2): (i32, i32, i32)| (r - r2).pow(2) + (g - g2).pow(2) + (b - b2).pow(2);
+        // The levels of each channel in the cube are 0, 95, 135, 175, 215 and 255.
+        let level = |c: i32| match c {
+            ..48 => 0,
+            48..115 => 1,
+            _ => (c - 35) / 40,
+        };
+        let value = |l: i32| if l == 0 { 0 } else { 55 + 40 * l };
+        let (lr, lg, lb) = (level(r), level(g), level(b));
+        let cube = (value(lr), value(lg), value(lb));
+        // The greys run from 8 to 238 in steps of 10.
+        let grey_level = (((r + g + b) / 3 - 3) / 10).clamp(0, 23);
+        let grey = 8 + 10 * grey_level;
+        if distance((grey, grey, grey)) < distance(cube) {
+            232 + grey_level as u8
+        } else {
+            16 + (36 * lr + 6 * lg + lb) as u8
+        }
+    }
+
+    /// The colour in true colour, taking the terminal's 256 colour palette to have xterm's
+    /// default colours.
+    #[cfg(feature = "window")]
+    pub fn rgb(self) -> (u8, u8, u8) {
+        /// xterm's 16 basic colours.
+        const BASIC: [(u8, u8, u8); 16] = [
+            (0x00, 0x00, 0x00),
+            (0xCD, 0x00, 0x00),
+            (0x00, 0xCD, 0x00),
+            (0xCD, 0xCD, 0x00),
+            (0x00, 0x00, 0xEE),
+            (0xCD, 0x00, 0xCD),
+            (0x00, 0xCD, 0xCD),
+            (0xE5, 0xE5, 0xE5),
+            (0x7F, 0x7F, 0x7F),
+            (0xFF, 0x00, 0x00),
+            (0x00, 0xFF, 0x00),
+            (0xFF, 0xFF, 0x00),
+            (0x5C, 0x5C, 0xFF),
+            (0xFF, 0x00, 0xFF),
+            (0x00, 0xFF, 0xFF),
+            (0xFF, 0xFF, 0xFF),
+        ];
+        let n = match self {
+            Self::Ansi(n) => n,
+            Self::Rgb(r, g, b) => return (r, g, b),
+        };
+        let value = |l: u8| if l == 0 { 0 } else { 55 + 40 * l };
+        match n {
+            ..16 => BASIC[n as usize],
+            16..232 => {
+                let n = n - 16;
+                (value(n / 36), value(n / 6 % 6), value(n % 6))
+            }
+            _ => {
+                let grey = 8 + 10 * (n - 232);
+                (grey, grey, grey)
+            }
+        }
+    }
+}
+
+impl FromStr for Colour {
+    type Err = ();
+
+    /// `RRGGBB` in hex, optionally after a `#`, or the number of a colour in the terminal's 256
+    /// colour palette.
+    fn from_str(s: &str) -> Result<Self, ()> {
+        let hex = s.strip_prefix('#').unwrap_or(s);
+        if hex.len() == 6 {
+            let rgb = u32::from_str_radix(hex, 16).map_err(|_| ())?;
+            return Ok(Self::Rgb((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8));
+        }
+        s.parse().map(Self::Ansi).map_err(|_| ())
+    }
+}
+
+/// Colours from the terminal's 256 colour palette.
+const fn ansi(palette: [u8; 4]) -> [Colour; 4] {
+    [
+        Colour::Ansi(palette[0]),
+        Colour::Ansi(palette[1]),
+        Colour::Ansi(palette[2]),
+        Colour::Ansi(palette[3]),
+    ]
+}
+
+/// Colours for the plain theme once a program draws in XO-CHIP's second plane, which the
+/// terminal's own colours can't tell apart from the first: black, white, red for the second
+/// plane and blue for both.
+pub const XOCHIP_PALETTE: [Colour; 4] = [
+    Colour::Rgb(0x00, 0x00, 0x00),
+    Colour::Rgb(0xFF, 0xFF, 0xFF),
+    Colour::Rgb(0xFF, 0x5F, 0x5F),
+    Colour::Rgb(0x5F, 0x87, 0xFF),
+];
+
+/// Colours to draw the display in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Theme {
+    /// Colours for each pixel value: unlit, lit in the first plane, lit in the second plane and
+    /// lit in both. With `None` the display is drawn in the terminal's own colours until anything
+    /// is drawn in the second plane, and then in [`XOCHIP_PALETTE`].
+    pub palette: Option<[Colour; 4]>,
+}
+
+impl Theme {
+    /// The terminal's own colours.
+    pub const PLAIN: Self = Self { palette: None };
+    /// Green phosphor.
+    pub const GREEN: Self = Self {
+        palette: Some(ansi([16, 46, 28, 157])),
+    };
+    /// Amber phosphor.
+    pub const AMBER: Self = Self {
+        palette: Some(ansi([16, 214, 130, 229])),
+    };
+    /// White on black, whatever the terminal's colours.
+    pub const WHITE: Self = Self {
+        palette: Some(ansi([16, 231, 244, 250])),
+    };
+    /// Black on white.
+    pub const INVERTED: Self = Self {
+        palette: Some(ansi([231, 16, 246, 240])),
+    };
+    /// Octo's default colours, which most XO-CHIP games are designed for.
+    pub const OCTO: Self = Self {
+        palette: Some(ansi([136, 220, 202, 52])),
+    };
+
+    /// A theme of exactly four comma-separated colours, see [`Colour::from_str`].
+    pub fn from_palette(s: &str) -> Result<Self, String> {
+        let colours = s
+            .split(',')
+            .map(|colour| {
+                colour
+                    .trim()
+                    .parse()
+                    .map_err(|()| format!("invalid colour `{colour}`"))
+            })
+            .collect::<Result<Vec<Colour>, _>>()?;
+        let palette = colours
+            .try_into()
+            .map_err(|colours: Vec<_>| format!("expected 4 colours but got {}", colours.len()))?;
+        Ok(Self {
+            palette: Some(palette),
+        })
+    }
+}
+
+impl FromStr for Theme {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "plain" => Ok(Self::PLAIN),
+            "green" => Ok(Self::GREEN),
+            "amber" => Ok(Self::AMBER),
+            "white" => Ok(Self::WHITE),
+            "inverted" => Ok(Self::INVERTED),
+            "octo" => Ok(Self::OCTO),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Hides the terminal's cursor, which would otherwise blink over the display, until dropped.
+pub struct HiddenCursor(());
+
+impl HiddenCursor {
+    pub fn hide() -> Self {
+        print!("\x1B[?25l");
+        drop(io::stdout().flush());
+        Self(())
+    }
+}
+
+impl Drop for HiddenCursor {
+    fn drop(&mut self) {
+        print!("\x1B[?25h");
+        drop(io::stdout().flush());
+    }
+}
+
+/// Set the foreground and background to colours `fg` and `bg`, approximated in the 256 colour
+/// palette, unless they're already `colours`.
+fn set_colours(
+    out: &mut impl Write,
+    colours: &mut Option<(u8, u8)>,
+    fg: Colour,
+    bg: Colour,
+) -> io::Result<()> {
+    let (fg, bg) = (fg.ansi(), bg.ansi());
+    if *colours != Some((fg, bg)) {
+        write!(out, "\x1B[38;5;{fg}m\x1B[48;5;{bg}m")?;
+        *colours = Some((fg, bg));
+    }
+    Ok(())
+}
+
+/// Draw `display` to `out` over the previous frame, centred in a terminal of `size` columns and
+/// rows if it's known.
+pub fn draw(
+    out: &mut impl Write,
+    display: &Display,
+    mode: RenderMode,
+    theme: Theme,
+    size: Option<(usize, usize)>,
+) -> io::Result<()> {
+    let (width, height) = display.dimensions();
+    let rows = mode.rows_for(height);
+    let margin = match size {
+        Some((columns, terminal_rows)) if columns < width || terminal_rows < rows => {
+            // Drawing anyway would wrap the lines and scroll the terminal.
+            write!(
+                out,
+                "\x1B[2J{RESET_CURSOR}terminal too small: the display needs {width}x{rows} \
+                 characters but there are {columns}x{terminal_rows}"
+            )?;
+            return out.flush();
+        }
+        Some((columns, _)) => (columns - width) / 2,
+        None => 0,
+    };
+    // Each line is cleared first in case the display was further left before the terminal was
+    // resized.
+    let indent = if margin > 0 {
+        format!("\x1B[2K\x1B[{margin}C")
+    } else {
+        "\x1B[2K".to_owned()
+    };
+    let palette = theme.palette.or_else(|| {
+        display
+            .pixels()
+            .iter()
+            .any(|&p| p > 1)
+            .then_some(XOCHIP_PALETTE)
+    });
+    write!(out, "{RESET_CURSOR}")?;
+    match mode {
+        RenderMode::HalfBlock => {
+            for y in (0..height).step_by(2) {
+                write!(out, "{indent}")?;
+                let mut colours = None;
+                for x in 0..width {
+                    let (top, bottom) = (display.get(x, y), display.get(x, y + 1));
+                    match palette {
+                        // The upper half block is drawn with the top pixel as the foreground and
+                        // the bottom pixel as the background.
+                        Some(palette) => {
+                            set_colours(
+                                out,
+                                &mut colours,
+                                palette[top as usize & 3],
+                                palette[bottom as usize & 3],
+                            )?;
+                            write!(out, "\u{2580}")?;
+                        }
+                        None => write!(
+                            out,
+                            "{}",
+                            match (top != 0, bottom != 0) {
+                                (false, false) => " ",
+                                (true, false) => "\u{2580}",
+                                (false, true) => "\u{2584}",
+                                (true, true) => "\u{2588}",
+                            }
+                        )?,
+                    }
+                }
+                writeln!(out, "{RESET_COLOURS}\x1B[K")?;
+            }
+        }
+        RenderMode::Ascii => {
+            for row in display.pixels().chunks(width) {
+                write!(out, "{indent}")?;
+                let mut colours = None;
+                for &pixel in row {
+                    if let Some(palette) = palette {
+                        set_colours(out, &mut colours, palette[pixel as usize & 3], palette[0])?;
+                    }
+                    write!(out, "{}", if pixel == 0 { ' ' } else { '#' })?;
+                }
+                writeln!(out, "{RESET_COLOURS}\x1B[K")?;
+            }
+        }
+    }
+    // Blank out what's left of a larger frame from before switching to low resolution.
+    for _ in rows..mode.rows() {
+        writeln!(out, "\x1B[2K")?;
+    }
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Draw `pixels`, `width` pixels wide, into a string rather than the terminal.
+    fn render(
+        pixels: &[u8],
+        width: usize,
+        mode: RenderMode,
+        theme: Theme,
+        size: Option<(usize, usize)>,
+    ) -> String {
+        let mut display = Display::new(width, pixels.len() / width);
+        for (i, &pixel) in pixels.iter().enumerate() {
+            display.set(i % width, i / width, pixel);
+        }
+        let mut out = Vec::new();
+        draw(&mut out, &display, mode, theme, size).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn half_blocks() {
+        // A 2x2 display with the top left and bottom right pixels lit.
+        let out = render(&[1, 0, 0, 1], 2, RenderMode::HalfBlock, Theme::PLAIN, None);
+        let mut lines = out.lines();
+        assert_eq!(
+            lines.next(),
+            Some("\x1B[1;1H\x1B[2K\u{2580}\u{2584}\x1B[0m\x1B[K")
+        );
+        // The rest of the rows set aside for the high resolution display are blanked.
+        assert_eq!(lines.clone().count(), RenderMode::HalfBlock.rows() - 1);
+        assert!(lines.all(|line| line == "\x1B[2K"));
+    }
+
+    #[test]
+    fn centred_with_colours() {
+        let out = render(&[0, 2, 2], 3, RenderMode::Ascii, Theme::OCTO, Some((7, 64)));
+        assert_eq!(
+            out.lines().next(),
+            Some(
+                "\x1B[1;1H\x1B[2K\x1B[2C\x1B[38;5;136m\x1B[48;5;136m \
+                 \x1B[38;5;202m\x1B[48;5;136m##\x1B[0m\x1B[K"
+            )
+        );
+    }
+
+    #[test]
+    fn second_plane_colours() {
+        // The plain theme switches to colours once the second plane is drawn in.
+        let out = render(&[0, 1], 2, RenderMode::Ascii, Theme::PLAIN, None);
+        assert!(out.starts_with("\x1B[1;1H\x1B[2K #\x1B[0m"));
+        let out = render(&[0, 2], 2, RenderMode::Ascii, Theme::PLAIN, None);
+        assert!(out.starts_with(
+            "\x1B[1;1H\x1B[2K\x1B[38;5;16m\x1B[48;5;16m \x1B[38;5;203m\x1B[48;5;16m#"
+        ));
+    }
+
+    #[test]
+    fn approximate_colours() {
+        assert_eq!(Colour::Ansi(42).ansi(), 42);
+        assert_eq!(Colour::Rgb(0, 0, 0).ansi(), 16);
+        assert_eq!(Colour::Rgb(255, 255, 255).ansi(), 231);
+        assert_eq!(Colour::Rgb(255, 0, 0).ansi(), 196);
+        assert_eq!(Colour::Rgb(0x5F, 0x87, 0xFF).ansi(), 69);
+        // Greys between the cube's levels come from the greyscale ramp.
+        assert_eq!(Colour::Rgb(0x80, 0x80, 0x80).ansi(), 244);
+        assert_eq!("#FF8000".parse(), Ok(Colour::Rgb(255, 128, 0)));
+        assert_eq!("231".parse(), Ok(Colour::Ansi(231)));
+        assert_eq!("ff".parse::<Colour>(), Err(()));
+    }
+
+    #[cfg(feature = "window")]
+    #[test]
+    fn true_colours() {
+        assert_eq!(Colour::Rgb(1, 2, 3).rgb(), (1, 2, 3));
+        assert_eq!(Colour::Ansi(9).rgb(), (255, 0, 0));
+        assert_eq!(Colour::Ansi(16).rgb(), (0, 0, 0));
+        assert_eq!(Colour::Ansi(69).rgb(), (0x5F, 0x87, 0xFF));
+        assert_eq!(Colour::Ansi(231).rgb(), (255, 255, 255));
+        assert_eq!(Colour::Ansi(244).rgb(), (0x80, 0x80, 0x80));
+        // Colours from the cube and ramp come back the same.
+        for n in 16..=255 {
+            let (r, g, b) = Colour::Ansi(n).rgb();
+            assert_eq!(Colour::Rgb(r, g, b).ansi(), n);
+        }
+    }
+
+    #[test]
+    fn too_small() {
+        let out = render(&[0; 8], 4, RenderMode::Ascii, Theme::PLAIN, Some((3, 64)));
+        assert!(out.ends_with("needs 4x2 characters but there are 3x64"));
+    }
+}
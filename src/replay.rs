@@ -0,0 +1,245 @@
+//! Recording keypad input to a file and replaying it.
+//!
+//! The log starts with the seed of the random number generator, so that the replay sees the same
+//! random numbers, followed by a line for every key pressed or released: the cycle it happened on,
+//! counted in instructions executed, the keypad key and `down` or `up`. Each 60Hz tick of the
+//! timers is logged too, as its cycle and `tick`, so that a program polling the delay timer sees
+//! the same values however fast the replay runs. For example:
+//!
+//! ```text
+//! seed,255
+//! 1522,tick
+//! 1534,A,down
+//! 1545,tick
+//! 1702,A,up
+//! ```
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    str::FromStr,
+};
+
+/// A keypad key going down or up before the instruction numbered `cycle` is executed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub cycle: u64,
+    pub key: u8,
+    pub down: bool,
+}
+
+impl fmt::Display for KeyEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = if self.down { "down" } else { "up" };
+        write!(f, "{},{:X},{state}", self.cycle, self.key)
+    }
+}
+
+impl FromStr for KeyEvent {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let invalid = || format!("invalid key event `{s}`");
+        let mut fields = s.split(',');
+        let (Some(cycle), Some(key), Some(state), None) =
+            (fields.next(), fields.next(), fields.next(), fields.next())
+        else {
+            return Err(invalid());
+        };
+        let key = u8::from_str_radix(key, 16)
+            .ok()
+            .filter(|&k| k < 16)
+            .ok_or_else(invalid)?;
+        Ok(Self {
+            cycle: cycle.parse().map_err(|_| invalid())?,
+            key,
+            down: match state {
+                "down" => true,
+                "up" => false,
+                _ => return Err(invalid()),
+            },
+        })
+    }
+}
+
+/// Writes key events to a log as they happen.
+pub struct InputRecorder {
+    out: BufWriter<File>,
+}
+
+impl InputRecorder {
+    /// Start a log at `path` for a run with the random number generator seeded with `seed`.
+    pub fn create(path: &Path, seed: u8) -> io::Result<Self> {
+        let mut out = BufWriter::new(File::create(path)?);
+        writeln!(out, "seed,{seed}")?;
+        Ok(Self { out })
+    }
+
+    pub fn record(&mut self, event: KeyEvent) -> io::Result<()> {
+        writeln!(self.out, "{event}")
+    }
+
+    /// Log a tick of the timers before the instruction numbered `cycle`.
+    pub fn record_tick(&mut self, cycle: u64) -> io::Result<()> {
+        writeln!(self.out, "{cycle},tick")
+    }
+}
+
+/// Key events and timer ticks read back from a log, to be fed to the machine at the cycles they
+/// were recorded on.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Replay {
+    pub seed: Option<u8>,
+    events: VecDeque<KeyEvent>,
+    /// The cycles the timers ticked before.
+    ticks: VecDeque<u64>,
+}
+
+impl Replay {
+    pub fn open(path: &Path) -> Result<Self, String> {
+        std::fs::read_to_string(path)
+            .map_err(|e| e.to_string())?
+            .parse()
+    }
+
+    /// Take the events which should have happened by `cycle`.
+    pub fn due(&mut self, cycle: u64) -> impl Iterator<Item = KeyEvent> + '_ {
+        std::iter::from_fn(move || {
+            self.events
+                .front()
+                .is_some_and(|event| event.cycle <= cycle)
+                .then(|| self.events.pop_front().expect("there is a front"))
+        })
+    }
+
+    /// Take the timer ticks which should have happened by `cycle`, returning how many there were.
+    pub fn ticks_due(&mut self, cycle: u64) -> usize {
+        let due = self.ticks.iter().take_while(|&&tick| tick <= cycle).count();
+        self.ticks.drain(..due);
+        due
+    }
+
+    /// Whether every event and tick has been replayed.
+    pub fn finished(&self) -> bool {
+        self.events.is_empty() && self.ticks.is_empty()
+    }
+}
+
+impl FromStr for Replay {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let mut lines = s.lines().peekable();
+        let seed = match lines.peek().and_then(|line| line.strip_prefix("seed,")) {
+            Some(seed) => {
+                let seed = seed.parse().map_err(|_| format!("invalid seed `{seed}`"))?;
+                lines.next();
+                Some(seed)
+            }
+            None => None,
+        };
+        let mut events = VecDeque::new();
+        let mut ticks = VecDeque::new();
+        for line in lines.filter(|line| !line.is_empty()) {
+            match line.strip_suffix(",tick") {
+                Some(cycle) => ticks.push_back(
+                    cycle
+                        .parse()
+                        .map_err(|_| format!("invalid tick `{line}`"))?,
+                ),
+                None => events.push_back(line.parse::<KeyEvent>()?),
+            }
+        }
+        if events
+            .iter()
+            .zip(events.iter().skip(1))
+            .any(|(a, b)| a.cycle > b.cycle)
+            || ticks.iter().zip(ticks.iter().skip(1)).any(|(a, b)| a > b)
+        {
+            return Err("events are out of order".to_owned());
+        }
+        Ok(Self {
+            seed,
+            events,
+            ticks,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_events() {
+        let event = KeyEvent {
+            cycle: 1534,
+            key: 0xA,
+            down: true,
+        };
+        assert_eq!(event.to_string(), "1534,A,down");
+        assert_eq!("1534,a,down".parse(), Ok(event));
+        for invalid in [
+            "1534,A",
+            "1534,A,down,up",
+            "x,A,down",
+            "1534,10,down",
+            "1,A,left",
+        ] {
+            assert!(invalid.parse::<KeyEvent>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn replay() {
+        let mut replay: Replay = "seed,7\n10,1,down\n10,2,down\n25,1,up\n".parse().unwrap();
+        assert_eq!(replay.seed, Some(7));
+        assert_eq!(replay.due(9).count(), 0);
+        let keys: Vec<_> = replay.due(12).map(|e| (e.key, e.down)).collect();
+        assert_eq!(keys, [(1, true), (2, true)]);
+        assert!(!replay.finished());
+        assert_eq!(replay.due(100).count(), 1);
+        assert!(replay.finished());
+
+        assert_eq!("".parse::<Replay>().unwrap().seed, None);
+        assert!("5,1,down\n4,1,up".parse::<Replay>().is_err());
+    }
+
+    #[test]
+    fn ticks() {
+        let mut replay: Replay = "seed,7\n5,tick\n8,1,down\n12,tick\n12,tick\n"
+            .parse()
+            .unwrap();
+        assert_eq!(replay.ticks_due(4), 0);
+        assert_eq!(replay.ticks_due(5), 1);
+        assert_eq!(replay.due(8).count(), 1);
+        // Ticks while waiting for a frame all land before the same instruction.
+        assert_eq!(replay.ticks_due(12), 2);
+        assert!(replay.finished());
+
+        assert!("x,tick".parse::<Replay>().is_err());
+        assert!("5,tick\n4,tick".parse::<Replay>().is_err());
+    }
+
+    #[test]
+    fn round_trip() {
+        let path = std::env::temp_dir().join(format!("chip8-replay-{}.log", std::process::id()));
+        let mut recorder = InputRecorder::create(&path, 255).unwrap();
+        let event = KeyEvent {
+            cycle: 3,
+            key: 0xF,
+            down: false,
+        };
+        recorder.record(event).unwrap();
+        recorder.record_tick(4).unwrap();
+        drop(recorder);
+        let mut replay = Replay::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(replay.seed, Some(255));
+        assert_eq!(replay.due(3).collect::<Vec<_>>(), [event]);
+        assert_eq!(replay.ticks_due(4), 1);
+    }
+}
@@ -0,0 +1,141 @@
+//! Saving the display as a PNG image.
+//!
+//! The image is 1-bit greyscale with lit pixels white. To stay within the standard library the
+//! image data is stored uncompressed, which for a monochrome image is still only a few kilobytes.
+
+use std::{fs, io, path::Path};
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+
+/// The most bytes a stored deflate block can hold.
+const MAX_STORED: usize = 0xFFFF;
+
+/// Write `display`, `width` by `height` pixels, to a PNG file at `path` with each pixel drawn as a
+/// `scale` by `scale` square.
+pub fn framebuffer_to_png(
+    display: &[u8],
+    width: usize,
+    height: usize,
+    scale: usize,
+    path: &Path,
+) -> io::Result<()> {
+    fs::write(path, encode(display, width, height, scale))
+}
+
+/// Encode `display` as a PNG image.
+fn encode(display: &[u8], width: usize, height: usize, scale: usize) -> Vec<u8> {
+    let (image_width, image_height) = (width * scale, height * scale);
+
+    // Each scanline is a filter type byte of 0 (none) followed by the pixels, packed 8 to a byte
+    // with the leftmost pixel in the high bit.
+    let stride = 1 + image_width.div_ceil(8);
+    let mut raw = Vec::with_capacity(stride * image_height);
+    for row in display.chunks(width).take(height) {
+        let mut line = vec![0; stride];
+        for x in 0..image_width {
+            if row[x / scale] != 0 {
+                line[1 + x / 8] |= 0x80 >> (x % 8);
+            }
+        }
+        for _ in 0..scale {
+            raw.extend_from_slice(&line);
+        }
+    }
+
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&(image_width as u32).to_be_bytes());
+    ihdr.extend_from_slice(&(image_height as u32).to_be_bytes());
+    // Bit depth 1, greyscale, deflate compression, adaptive filtering, no interlace.
+    ihdr.extend_from_slice(&[1, 0, 0, 0, 0]);
+
+    let mut png = SIGNATURE.to_vec();
+    chunk(&mut png, b"IHDR", &ihdr);
+    chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+    chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+/// Append a chunk of type `kind` holding `data` to `png`.
+fn chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// Wrap `data` in a zlib stream of uncompressed deflate blocks.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let blocks = data.len().div_ceil(MAX_STORED).max(1);
+    let mut out = Vec::with_capacity(2 + blocks * 5 + data.len() + 4);
+    // Deflate with a 32K window and no preset dictionary.
+    out.extend_from_slice(&[0x78, 0x01]);
+    let mut chunks = data.chunks(MAX_STORED).peekable();
+    if chunks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = chunks.next() {
+        let last = chunks.peek().is_none();
+        let len = block.len() as u16;
+        out.push(last as u8);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                crc >> 1 ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    b << 16 | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksums() {
+        assert_eq!(crc32(b"IEND"), 0xAE42_6082);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+
+    #[test]
+    fn png() {
+        // A 2x2 display with the top left and bottom right pixels lit, scaled up 3 times.
+        let png = encode(&[1, 0, 0, 1], 2, 2, 3);
+        assert_eq!(png[..8], SIGNATURE);
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(png[16..20], 6u32.to_be_bytes());
+        assert_eq!(png[20..24], 6u32.to_be_bytes());
+        assert_eq!(&png[png.len() - 8..png.len() - 4], b"IEND");
+
+        // The image data follows the zlib header and the single stored block's header.
+        assert_eq!(&png[37..41], b"IDAT");
+        let data = &png[41 + 2 + 5..];
+        let rows = [[0, 0b1110_0000], [0, 0b0001_1100]];
+        let expected: Vec<u8> = rows.iter().flat_map(|row| row.repeat(3)).collect();
+        assert_eq!(data[..12], expected);
+    }
+}
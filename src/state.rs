@@ -0,0 +1,243 @@
+//! Snapshots of the whole machine state.
+//!
+//! The layout is fixed, with multi-byte values big-endian:
+//!
+//! | bytes  | contents                                  |
+//! |--------|-------------------------------------------|
+//! | 4      | magic `C8ST`                              |
+//! | 1      | version, currently 6                      |
+//! | 4      | memory size m                             |
+//! | m      | memory                                    |
+//! | 1      | 1 in high resolution, 0 otherwise         |
+//! | w * h  | display, one byte per pixel               |
+//! | 1      | selected planes                           |
+//! | 2      | PC                                        |
+//! | 2      | I                                         |
+//! | 1      | delay timer                               |
+//! | 1      | sound timer                               |
+//! | 16     | V0-VF                                     |
+//! | 1      | PRNG state                                |
+//! | 8      | RPL flags                                 |
+//! | 1      | 1 with an audio pattern, 0 otherwise      |
+//! | 16     | audio pattern                             |
+//! | 1      | pitch                                     |
+//! | 1      | stack depth n                             |
+//! | 2n     | stack, outermost first                    |
+
+use alloc::vec::Vec;
+
+use crate::{Chip8, Chip8Error, Lfsr, HEIGHT, HIRES_HEIGHT, HIRES_WIDTH, RECENT_FETCHES, WIDTH};
+
+const MAGIC: &[u8; 4] = b"C8ST";
+const VERSION: u8 = 6;
+
+/// Reads fields off the front of a snapshot.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], Chip8Error> {
+        if self.0.len() < n {
+            return Err(Chip8Error::InvalidState("snapshot is truncated"));
+        }
+        let (bytes, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, Chip8Error> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, Chip8Error> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, Chip8Error> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+}
+
+impl Chip8 {
+    /// Serialize the machine state so that it can be restored with [`Chip8::load_state`].
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = Vec::new();
+        self.save_state_into(&mut state);
+        state
+    }
+
+    /// Like [`Chip8::save_state`], but serializes into `state`, replacing what was there, so that
+    /// a buffer can be reused for many snapshots without allocating each time.
+    pub fn save_state_into(&self, state: &mut Vec<u8>) {
+        state.clear();
+        state.reserve(
+            4 + 1
+                + 4
+                + self.memory.len()
+                + 1
+                + self.display.pixels().len()
+                + 51
+                + 2 * self.stack.len(),
+        );
+        state.extend_from_slice(MAGIC);
+        state.push(VERSION);
+        state.extend_from_slice(&(self.memory.len() as u32).to_be_bytes());
+        state.extend_from_slice(&self.memory[..]);
+        state.push((self.display.width() == HIRES_WIDTH) as u8);
+        state.extend_from_slice(self.display.pixels());
+        state.push(self.planes);
+        state.extend_from_slice(&self.pc.to_be_bytes());
+        state.extend_from_slice(&self.ri.to_be_bytes());
+        state.push(self.timers.delay());
+        state.push(self.timers.sound());
+        state.extend_from_slice(&self.rv);
+        state.push(self.prng.0);
+        state.extend_from_slice(&self.rpl);
+        state.push(self.audio.is_some() as u8);
+        state.extend_from_slice(&self.audio.unwrap_or_default());
+        state.push(self.pitch);
+        state.push(self.stack.len() as u8);
+        for addr in &self.stack {
+            state.extend_from_slice(&addr.to_be_bytes());
+        }
+    }
+
+    /// Restore a snapshot taken with [`Chip8::save_state`]. On error the machine is left
+    /// unchanged.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), Chip8Error> {
+        let mut reader = Reader(state);
+        if reader.bytes(MAGIC.len())? != MAGIC {
+            return Err(Chip8Error::InvalidState("not a snapshot"));
+        }
+        if reader.u8()? != VERSION {
+            return Err(Chip8Error::InvalidState(
+                "snapshot is from an incompatible version",
+            ));
+        }
+        if reader.u32()? as usize != self.memory.len() {
+            return Err(Chip8Error::InvalidState(
+                "snapshot is from a machine with a different amount of memory",
+            ));
+        }
+        let memory = reader.bytes(self.memory.len())?;
+        let (width, height) = match reader.u8()? {
+            0 => (WIDTH, HEIGHT),
+            1 => (HIRES_WIDTH, HIRES_HEIGHT),
+            _ => {
+                return Err(Chip8Error::InvalidState(
+                    "snapshot has an invalid resolution",
+                ))
+            }
+        };
+        let display = reader.bytes(width * height)?;
+        let planes = reader.u8()?;
+        let pc = reader.u16()?;
+        let ri = reader.u16()?;
+        let delay_timer = reader.u8()?;
+        let sound_timer = reader.u8()?;
+        let rv = reader.bytes(16)?;
+        let prng = reader.u8()?;
+        let rpl = reader.bytes(8)?;
+        let has_audio = reader.u8()?;
+        let audio = reader.bytes(16)?;
+        let pitch = reader.u8()?;
+        let stack = (0..reader.u8()?)
+            .map(|_| reader.u16())
+            .collect::<Result<Vec<_>, _>>()?;
+        if !reader.0.is_empty() {
+            return Err(Chip8Error::InvalidState("snapshot has trailing data"));
+        }
+        if display.iter().any(|&p| p > 3) {
+            return Err(Chip8Error::InvalidState("snapshot has invalid pixels"));
+        }
+        let audio = match has_audio {
+            0 => None,
+            1 => Some(audio.try_into().expect("16 bytes")),
+            _ => {
+                return Err(Chip8Error::InvalidState(
+                    "snapshot has an invalid audio flag",
+                ))
+            }
+        };
+        if planes > 3 {
+            return Err(Chip8Error::InvalidState("snapshot has invalid planes"));
+        }
+        if stack.len() > self.quirks.stack_depth {
+            return Err(Chip8Error::InvalidState(
+                "snapshot has more calls on the stack than fit",
+            ));
+        }
+
+        self.memory.copy_from_slice(memory);
+        self.display.resize(width, height);
+        self.display.pixels_mut().copy_from_slice(display);
+        self.planes = planes;
+        self.pc = pc;
+        self.ri = ri;
+        self.timers.set_delay(delay_timer);
+        self.timers.set_sound(sound_timer);
+        self.rv.copy_from_slice(rv);
+        self.prng = Lfsr::with_seed(prng);
+        self.rpl.copy_from_slice(rpl);
+        self.audio = audio;
+        self.pitch = pitch;
+        self.stack = stack;
+        self.waiting_for_frame = false;
+        self.recent_fetches = [None; RECENT_FETCHES];
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Quirks;
+
+    #[test]
+    fn round_trip() {
+        let mut chip8 = Chip8::new(Quirks::default());
+        // Call a subroutine which switches to high resolution, sets up some registers and draws.
+        chip8
+            .load_rom(&[
+                0x22, 0x04, 0x00, 0x00, 0x00, 0xFF, 0x61, 0x1F, 0xA0, 0x4F, 0xD1, 0x15,
+            ])
+            .unwrap();
+        for _ in 0..5 {
+            chip8.step().unwrap();
+        }
+        let state = chip8.save_state();
+
+        let mut restored = Chip8::new(Quirks::default());
+        restored.load_state(&state).unwrap();
+        assert_eq!(restored.save_state(), state);
+        assert_eq!(restored.pc, 0x20C);
+        assert_eq!(restored.display.width(), HIRES_WIDTH);
+        assert_eq!(restored.stack, [0x202]);
+        assert_eq!(restored.display, chip8.display);
+    }
+
+    #[test]
+    fn rejects_bad_snapshots() {
+        let mut chip8 = Chip8::new(Quirks::default());
+        let mut state = chip8.save_state();
+        assert!(chip8.load_state(&state[..100]).is_err());
+        assert!(chip8.load_state(b"hello").is_err());
+        state[4] = VERSION + 1;
+        assert_eq!(
+            chip8.load_state(&state),
+            Err(Chip8Error::InvalidState(
+                "snapshot is from an incompatible version"
+            ))
+        );
+
+        // Snapshots only restore onto machines with the same amount of memory.
+        let state = Chip8::new(Quirks::xochip()).save_state();
+        assert_eq!(
+            chip8.load_state(&state),
+            Err(Chip8Error::InvalidState(
+                "snapshot is from a machine with a different amount of memory"
+            ))
+        );
+    }
+}
@@ -0,0 +1,131 @@
+//! Logging of executed instructions to a file.
+//!
+//! Each instruction is one line of space-separated columns: the cycle number, PC, opcode, its
+//! pattern (e.g. `DXYN`), the mnemonic, then `;` and the registers it named or changed. For
+//! example:
+//!
+//! ```text
+//! 00000012 0218 D01F DXYN DRW V0, V1, 15 ; V0=0C V1=08 VF=00
+//! ```
+//!
+//! An instruction which fails is logged with `error:` and the reason in place of the registers.
+
+use std::{
+    fmt::Write as _,
+    fs::{File, OpenOptions},
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use chip8::{disasm, Chip8, Chip8Error, StepOutcome};
+
+pub struct Tracer {
+    out: BufWriter<File>,
+    cycle: u64,
+    /// The PC, opcode, registers and I from before the instruction being traced.
+    pc: u16,
+    opcode: u16,
+    registers: [u8; 16],
+    index: u16,
+}
+
+impl Tracer {
+    /// Start tracing to the end of the file at `path`, creating it if needed.
+    pub fn append(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            out: BufWriter::new(file),
+            cycle: 0,
+            pc: 0,
+            opcode: 0,
+            registers: [0; 16],
+            index: 0,
+        })
+    }
+
+    /// Remember the state of `chip8` before executing an instruction.
+    pub fn before(&mut self, chip8: &Chip8) {
+        let pc = chip8.pc() as usize;
+        self.pc = chip8.pc();
+        self.opcode = match chip8.memory().get(pc..pc + 2) {
+            Some(&[hi, lo]) => u16::from_be_bytes([hi, lo]),
+            _ => 0,
+        };
+        self.registers = *chip8.registers();
+        self.index = chip8.index();
+    }
+
+    /// Log the instruction just executed by `chip8`, which had `result`.
+    pub fn after(
+        &mut self,
+        chip8: &Chip8,
+        result: &Result<StepOutcome, Chip8Error>,
+    ) -> io::Result<()> {
+        // Nothing was executed.
+        if *result == Ok(StepOutcome::WaitingForFrame) {
+            return Ok(());
+        }
+        let opcode = self.opcode;
+        let pattern = disasm::pattern(opcode).unwrap_or("????");
+        let mnemonic = disasm::mnemonic(opcode).unwrap_or_else(|| format!("DW #{opcode:04X}"));
+        let mut line = format!(
+            "{:08} {:04X} {opcode:04X} {pattern} {mnemonic} ;",
+            self.cycle, self.pc
+        );
+        match result {
+            Ok(_) => {
+                let x = (opcode >> 8 & 0xf) as usize;
+                let y = (opcode >> 4 & 0xf) as usize;
+                for (i, &v) in chip8.registers().iter().enumerate() {
+                    let named =
+                        (i == x && pattern.contains('X')) || (i == y && pattern.contains('Y'));
+                    if named || v != self.registers[i] {
+                        write!(line, " V{i:X}={v:02X}").unwrap();
+                    }
+                }
+                if chip8.index() != self.index {
+                    write!(line, " I={:04X}", chip8.index()).unwrap();
+                }
+            }
+            Err(e) => write!(line, " error: {e}").unwrap(),
+        }
+        self.cycle += 1;
+        writeln!(self.out, "{line}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use chip8::Quirks;
+
+    use super::*;
+
+    #[test]
+    fn trace_lines() {
+        let path = std::env::temp_dir().join(format!("chip8-trace-{}.log", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut chip8 = Chip8::new(Quirks::default());
+        chip8
+            .load_rom(&[0x61, 0x1F, 0x81, 0x14, 0xA3, 0x00, 0x00, 0x00])
+            .unwrap();
+        let mut tracer = Tracer::append(&path).unwrap();
+        for _ in 0..4 {
+            tracer.before(&chip8);
+            let result = chip8.step();
+            tracer.after(&chip8, &result).unwrap();
+        }
+        drop(tracer);
+
+        let trace = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            trace,
+            "00000000 0200 611F 6XNN LD V1, #1F ; V1=1F\n\
+             00000001 0202 8114 8XY4 ADD V1, V1 ; V1=3E\n\
+             00000002 0204 A300 ANNN LD I, #300 ; I=0300\n\
+             00000003 0206 0000 ???? DW #0000 ; error: unknown opcode 0000 at 0x206\n"
+        );
+    }
+}
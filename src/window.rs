@@ -0,0 +1,230 @@
+//! Drawing the display in a window rather than the terminal, with every pixel a square of the same
+//! size, for games which look bad in half-blocks.
+//!
+//! The window is opened and drawn by its own thread, which also reads the keys pressed in it. It
+//! draws the latest display every frame rather than waiting to be woken like the terminal's draw
+//! thread, since the window needs updating regularly anyway to handle its events.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Sender},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+};
+
+use chip8::{Display, HIRES_HEIGHT, HIRES_WIDTH};
+use minifb::{Key, KeyRepeat, Window, WindowOptions};
+
+use crate::{
+    input::{Input, KeyMap},
+    render::{Theme, XOCHIP_PALETTE},
+};
+
+/// How many times a second the window is drawn and its keys read.
+const FPS: usize = 60;
+
+/// Open a window titled `title` on its own thread, drawing each pixel of the high resolution
+/// display as a `scale` by `scale` square and low resolution pixels twice that size, in the
+/// colours of `theme`. Every frame the thread draws the display which `latest` copies into the one
+/// it's given, and sends the keys pressed in the window over `tx`, mapped with `keys`. It finishes
+/// once `shutdown` is set, after sending [`Input::Quit`] if the window is closed first.
+pub fn spawn<T: From<Input> + Send + 'static>(
+    title: String,
+    scale: usize,
+    theme: Theme,
+    keys: KeyMap,
+    mut latest: impl FnMut(&mut Display) + Send + 'static,
+    tx: Sender<T>,
+    shutdown: Arc<AtomicBool>,
+) -> Result<JoinHandle<()>, String> {
+    // The window has to be opened on the thread that draws it.
+    let (opened_tx, opened_rx) = mpsc::channel();
+    let thread = thread::spawn(move || {
+        let (width, height) = (HIRES_WIDTH * scale, HIRES_HEIGHT * scale);
+        let mut window = match Window::new(&title, width, height, WindowOptions::default()) {
+            Ok(window) => {
+                let _ = opened_tx.send(Ok(()));
+                window
+            }
+            Err(e) => {
+                let _ = opened_tx.send(Err(e.to_string()));
+                return;
+            }
+        };
+        window.set_target_fps(FPS);
+        // Without a theme, the plain terminal's colours are white on black.
+        let palette = theme.palette.unwrap_or(XOCHIP_PALETTE).map(|colour| {
+            let (r, g, b) = colour.rgb();
+            u32::from_be_bytes([0, r, g, b])
+        });
+        let mut display = Display::default();
+        let mut buffer = vec![0; width * height];
+        let mut held = [false; 16];
+        let mut turbo = false;
+        let mut quit = false;
+        while !shutdown.load(Ordering::Relaxed) {
+            latest(&mut display);
+            draw(&display, palette, &mut buffer, width);
+            // There's nowhere to report a failure to draw.
+            drop(window.update_with_buffer(&buffer, width, height));
+            if quit {
+                continue;
+            }
+            let mut inputs = Vec::new();
+            if window.is_open() {
+                let now_held = keypad(&window.get_keys(), &keys);
+                inputs.extend(changes(held, now_held));
+                held = now_held;
+                if window.is_key_down(Key::Tab) != turbo {
+                    turbo = !turbo;
+                    inputs.push(Input::Turbo(turbo));
+                }
+                // Keypad keys take priority so that any key can be mapped onto the keypad.
+                inputs.extend(
+                    window
+                        .get_keys_pressed(KeyRepeat::No)
+                        .into_iter()
+                        .filter(|&key| key_char(key).and_then(|c| keys.keypad(c)).is_none())
+                        .filter_map(command),
+                );
+            } else {
+                inputs.push(Input::Quit);
+            }
+            quit = inputs.contains(&Input::Quit);
+            if inputs
+                .into_iter()
+                .any(|input| tx.send(input.into()).is_err())
+            {
+                return;
+            }
+        }
+    });
+    opened_rx
+        .recv()
+        .expect("window thread reports whether the window opened")?;
+    Ok(thread)
+}
+
+/// Draw `display` into `buffer`, an image `width` pixels wide, in the `0RGB` colours of `palette`,
+/// scaling each pixel up to fill the width.
+fn draw(display: &Display, palette: [u32; 4], buffer: &mut [u32], width: usize) {
+    let size = width / display.width();
+    for (y, row) in buffer.chunks_mut(width).enumerate() {
+        let pixels = &display.pixels()[y / size * display.width()..][..display.width()];
+        for (x, pixel) in row.iter_mut().enumerate() {
+            *pixel = palette[pixels[x / size] as usize & 3];
+        }
+    }
+}
+
+/// The character typed by `key`, for looking it up in a [`KeyMap`].
+fn key_char(key: Key) -> Option<char> {
+    match key {
+        // `Key0` to `Key9` and `A` to `Z` are numbered 0 to 35, like the digits of base 36.
+        key if (key as u32) < 36 => char::from_digit(key as u32, 36),
+        Key::Apostrophe => Some('\''),
+        Key::Backquote => Some('`'),
+        Key::Backslash => Some('\\'),
+        Key::Comma => Some(','),
+        Key::Equal => Some('='),
+        Key::LeftBracket => Some('['),
+        Key::Minus => Some('-'),
+        Key::Period => Some('.'),
+        Key::RightBracket => Some(']'),
+        Key::Semicolon => Some(';'),
+        Key::Slash => Some('/'),
+        Key::Space => Some(' '),
+        _ => None,
+    }
+}
+
+/// Which keypad keys are held down, given the keys held down in the window.
+fn keypad(held: &[Key], keys: &KeyMap) -> [bool; 16] {
+    let mut keypad = [false; 16];
+    for k in held.iter().filter_map(|&key| keys.keypad(key_char(key)?)) {
+        keypad[k as usize] = true;
+    }
+    keypad
+}
+
+/// The keypad presses and releases to get from `old` to `new`.
+fn changes(old: [bool; 16], new: [bool; 16]) -> impl Iterator<Item = Input> {
+    (0..16u8).filter_map(move |k| match (old[k as usize], new[k as usize]) {
+        (false, true) => Some(Input::Down(k)),
+        (true, false) => Some(Input::Up(k)),
+        _ => None,
+    })
+}
+
+/// What pressing `key` does, besides the keypad, with the same keys as in the terminal. Escape
+/// quits, like closing the window.
+fn command(key: Key) -> Option<Input> {
+    match key {
+        Key::Equal | Key::NumPadPlus => Some(Input::Faster),
+        Key::Minus | Key::NumPadMinus => Some(Input::Slower),
+        Key::Space => Some(Input::Pause),
+        Key::F1 => Some(Input::Reset),
+        Key::F5 => Some(Input::SaveState),
+        Key::F9 => Some(Input::LoadState),
+        Key::F12 => Some(Input::Screenshot),
+        Key::Escape => Some(Input::Quit),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys() {
+        assert_eq!(key_char(Key::Key0), Some('0'));
+        assert_eq!(key_char(Key::Key9), Some('9'));
+        assert_eq!(key_char(Key::A), Some('a'));
+        assert_eq!(key_char(Key::Z), Some('z'));
+        assert_eq!(key_char(Key::Comma), Some(','));
+        assert_eq!(key_char(Key::F1), None);
+
+        let keys = KeyMap::default();
+        let held = keypad(&[Key::Key1, Key::V, Key::P, Key::LeftShift], &keys);
+        assert!(held[0x1] && held[0xF]);
+        assert_eq!(held.iter().filter(|&&down| down).count(), 2);
+        let mut now = held;
+        now[0x1] = false;
+        now[0x5] = true;
+        assert_eq!(
+            changes(held, now).collect::<Vec<_>>(),
+            [Input::Up(0x1), Input::Down(0x5)]
+        );
+        assert_eq!(changes(now, now).next(), None);
+
+        assert_eq!(command(Key::Escape), Some(Input::Quit));
+        assert_eq!(command(Key::F12), Some(Input::Screenshot));
+        assert_eq!(command(Key::Q), None);
+    }
+
+    #[test]
+    fn scaled() {
+        let palette = [0, 1, 2, 3];
+        // A low resolution display with its top left pixel lit, at twice the high resolution scale.
+        let mut display = Display::default();
+        display.set(0, 0, 1);
+        let width = HIRES_WIDTH * 2;
+        let mut buffer = vec![0; width * HIRES_HEIGHT * 2];
+        draw(&display, palette, &mut buffer, width);
+        let lit: Vec<_> = (0..buffer.len())
+            .filter(|&i| buffer[i] == 1)
+            .map(|i| (i % width, i / width))
+            .collect();
+        let square: Vec<_> = (0..4).flat_map(|y| (0..4).map(move |x| (x, y))).collect();
+        assert_eq!(lit, square);
+        // High resolution pixels are half the size, filling the same window.
+        display.resize(HIRES_WIDTH, HIRES_HEIGHT);
+        display.set(HIRES_WIDTH - 1, HIRES_HEIGHT - 1, 2);
+        draw(&display, palette, &mut buffer, width);
+        assert_eq!(buffer.iter().filter(|&&pixel| pixel == 2).count(), 4);
+        assert_eq!(buffer.last(), Some(&2));
+    }
+}